@@ -0,0 +1,181 @@
+//! 渲染内核的基准测试: escape_time 在几类典型点上的耗时, render 在不同分辨率和线程数下的耗时,
+//! 以及 simd 和 interior_check 两个选项对单线程渲染的影响
+//!
+//! cargo bench --bench mandelbrot
+//! cargo bench --bench mandelbrot -- escape_time   只运行名字包含 escape_time 的基准
+//!
+//! 测量由 criterion 完成, 结果保存在 target/criterion 中, 下一次运行时会与上一次比较
+//! 每个基准都用 iter_custom 自己计时, 同时把耗时累计到一张表中, 全部运行完后打印成便于对比的表格
+//! 表格只包含这次实际运行了的基准, 平均耗时也算上了 criterion 预热时的迭代
+
+use concurrency::fractal::Mandelbrot;
+use concurrency::parallel::Backend;
+use concurrency::{escape_time, RenderOptions};
+use criterion::measurement::WallTime;
+use criterion::{black_box, Bencher, BenchmarkId, Criterion};
+use num::Complex;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// escape_time 使用的迭代上限
+const LIMIT: usize = 1000;
+
+/// (名字, c): 内部的点跑满 LIMIT 次迭代, 边界附近的点在逃逸前迭代几百次, 外部的点几次就逃逸了
+const POINTS: [(&str, Complex<f64>); 3] = [
+    ("interior", Complex { re: -0.5, im: 0.0 }),
+    ("boundary", Complex { re: -0.75, im: 0.01 }),
+    ("exterior", Complex { re: 1.0, im: 0.5 }),
+];
+
+const RESOLUTIONS: [(usize, usize); 3] = [(256, 192), (512, 384), (1024, 768)];
+const THREADS: [usize; 4] = [1, 2, 4, 8];
+const BACKENDS: [Backend; 2] = [Backend::Crossbeam, Backend::Rayon];
+
+/// 比较渲染选项时使用的分辨率, 单线程渲染, 只看内核本身的差别
+const VARIANT_BOUNDS: (usize, usize) = (512, 384);
+
+/// (名字, simd, interior_check)
+const VARIANTS: [(&str, bool, bool); 4] = [
+    ("plain", false, false),
+    ("simd", true, false),
+    ("interior-check", false, true),
+    ("simd+interior-check", true, true),
+];
+
+/// 渲染覆盖整个曼德博集的视口, 内外的点都有
+const UPPER_LEFT: Complex<f64> = Complex { re: -2.5, im: 1.5 };
+const LOWER_RIGHT: Complex<f64> = Complex { re: 1.5, im: -1.5 };
+
+/// 测量时累计下来的耗时: 基准的名字 (例如 "render-512x384/rayon/4") -> (总耗时, 迭代次数)
+type Timings = RefCell<BTreeMap<String, (Duration, u64)>>;
+
+/// 交给 criterion 测量 routine, 同时把每一批的耗时累计到 timings[id] 中
+fn measure<R>(bencher: &mut Bencher<WallTime>, timings: &Timings, id: &str, mut routine: impl FnMut() -> R) {
+    bencher.iter_custom(|iters| {
+        let start = Instant::now();
+        for _ in 0..iters {
+            black_box(routine());
+        }
+        let elapsed = start.elapsed();
+        let mut timings = timings.borrow_mut();
+        let (total, count) = timings.entry(id.to_string()).or_default();
+        *total += elapsed;
+        *count += iters;
+        elapsed
+    });
+}
+
+/// id 每次迭代的平均耗时, 单位为纳秒; 这次没有运行的基准返回 None
+fn mean_nanos(timings: &Timings, id: &str) -> Option<f64> {
+    let timings = timings.borrow();
+    let (total, count) = timings.get(id)?;
+    Some(total.as_nanos() as f64 / *count as f64)
+}
+
+fn bench_escape_time(criterion: &mut Criterion, timings: &Timings) {
+    let mut group = criterion.benchmark_group("escape_time");
+    let zero = Complex { re: 0.0, im: 0.0 };
+    for (name, c) in POINTS {
+        group.bench_function(name, |bencher| {
+            measure(bencher, timings, &format!("escape_time/{}", name), || {
+                escape_time(&Mandelbrot, zero, black_box(c), LIMIT, 2.0)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_render(criterion: &mut Criterion, timings: &Timings) {
+    let options = RenderOptions::default();
+    for bounds in RESOLUTIONS {
+        // 一次渲染就要几十毫秒, 减少采样次数, 否则一轮基准要跑很久
+        let name = format!("render-{}x{}", bounds.0, bounds.1);
+        let mut group = criterion.benchmark_group(&name);
+        group.sample_size(10).warm_up_time(Duration::from_secs(1));
+        let mut pixels = vec![0; bounds.0 * bounds.1];
+        for backend in BACKENDS {
+            for threads in THREADS {
+                let id = format!("{}/{}/{}", name, backend.name(), threads);
+                group.bench_with_input(BenchmarkId::new(backend.name(), threads), &threads, |bencher, &threads| {
+                    measure(bencher, timings, &id, || {
+                        backend.render(&mut pixels, bounds, UPPER_LEFT, LOWER_RIGHT, &options, threads).unwrap()
+                    })
+                });
+            }
+        }
+        group.finish();
+    }
+}
+
+fn bench_variants(criterion: &mut Criterion, timings: &Timings) {
+    let bounds = VARIANT_BOUNDS;
+    let mut group = criterion.benchmark_group("render-options");
+    group.sample_size(10).warm_up_time(Duration::from_secs(1));
+    let mut pixels = vec![0; bounds.0 * bounds.1];
+    for (name, simd, interior_check) in VARIANTS {
+        let options = RenderOptions { simd, interior_check, ..RenderOptions::default() };
+        group.bench_function(name, |bencher| {
+            measure(bencher, timings, &format!("render-options/{}", name), || {
+                Backend::Single.render(&mut pixels, bounds, UPPER_LEFT, LOWER_RIGHT, &options, 1).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// 打印对比表格: escape_time 给出每个点的耗时和迭代次数, render 的加速比以同一分辨率下同一后端的单线程为基准,
+/// 渲染选项的加速比以不打开任何选项的 plain 为基准
+fn print_tables(timings: &Timings) {
+    let zero = Complex { re: 0.0, im: 0.0 };
+    println!();
+    println!("{:<10} {:>10} {:>12} {:>10}", "point", "iterations", "time(ns)", "ns/iter");
+    for (name, c) in POINTS {
+        let Some(nanos) = mean_nanos(timings, &format!("escape_time/{}", name)) else { continue };
+        let iterations = escape_time(&Mandelbrot, zero, c, LIMIT, 2.0).unwrap_or(LIMIT);
+        println!("{:<10} {:>10} {:>12.1} {:>10.2}", name, iterations, nanos, nanos / iterations as f64);
+    }
+
+    println!();
+    println!("{:<10} {:<10} {:>7} {:>10} {:>8} {:>12}", "pixels", "backend", "threads", "mean(ms)", "speedup", "Mpixels/s");
+    for bounds in RESOLUTIONS {
+        for backend in BACKENDS {
+            let resolution = format!("{}x{}", bounds.0, bounds.1);
+            let baseline = mean_nanos(timings, &format!("render-{}/{}/1", resolution, backend.name()));
+            for threads in THREADS {
+                let id = format!("render-{}/{}/{}", resolution, backend.name(), threads);
+                let Some(nanos) = mean_nanos(timings, &id) else { continue };
+                let speedup = baseline.map_or("-".to_string(), |baseline| format!("{:.2}x", baseline / nanos));
+                println!(
+                    "{:<10} {:<10} {:>7} {:>10.2} {:>8} {:>12.1}",
+                    resolution,
+                    backend.name(),
+                    threads,
+                    nanos / 1e6,
+                    speedup,
+                    (bounds.0 * bounds.1) as f64 / nanos * 1000.0,
+                );
+            }
+        }
+    }
+
+    println!();
+    println!("{:<20} {:>10} {:>8} {:>12}", "options", "mean(ms)", "speedup", "Mpixels/s");
+    let baseline = mean_nanos(timings, "render-options/plain");
+    for (name, _, _) in VARIANTS {
+        let Some(nanos) = mean_nanos(timings, &format!("render-options/{}", name)) else { continue };
+        let speedup = baseline.map_or("-".to_string(), |baseline| format!("{:.2}x", baseline / nanos));
+        let pixels = (VARIANT_BOUNDS.0 * VARIANT_BOUNDS.1) as f64;
+        println!("{:<20} {:>10.2} {:>8} {:>12.1}", name, nanos / 1e6, speedup, pixels / nanos * 1000.0);
+    }
+}
+
+fn main() {
+    let timings = Timings::default();
+    let mut criterion = Criterion::default().configure_from_args();
+    bench_escape_time(&mut criterion, &timings);
+    bench_render(&mut criterion, &timings);
+    bench_variants(&mut criterion, &timings);
+    criterion.final_summary();
+    print_tables(&timings);
+}
@@ -0,0 +1,111 @@
+use crate::metadata::RenderRecord;
+use crate::viewport::Viewport;
+use crate::{write_image, RenderError, Renderer};
+use num::Complex;
+
+/// 生成从 start 到 end 的 frames 个缩放倍数, 相邻两帧之间的比例保持不变 (指数插值)
+///
+/// 这样每一帧放大的程度相同, 播放时看起来是匀速推进的
+pub fn frame_zooms(start: f64, end: f64, frames: usize) -> Vec<f64> {
+    assert!(start > 0.0 && end > 0.0 && frames > 0);
+    if frames == 1 {
+        return vec![start];
+    }
+    let ratio = end / start;
+    (0..frames)
+        .map(|frame| start * ratio.powf(frame as f64 / (frames - 1) as f64))
+        .collect()
+}
+
+#[test]
+fn test_frame_zooms() {
+    let zooms = frame_zooms(1.0, 1000.0, 4);
+    let expected = [1.0, 10.0, 100.0, 1000.0];
+    for (zoom, expected) in zooms.iter().zip(expected) {
+        assert!((zoom - expected).abs() < 1e-9 * expected, "{} vs {}", zoom, expected);
+    }
+    assert_eq!(frame_zooms(3.0, 5.0, 1), vec![3.0]);
+    // 也可以从近处缩小到远处
+    assert!((frame_zooms(100.0, 1.0, 3)[1] - 10.0).abs() < 1e-9);
+}
+
+/// 第 frame 帧的文件名, 例如 prefix 为 "frames/zoom_" 时第 7 帧是 "frames/zoom_00007.png"
+pub fn frame_filename(prefix: &str, frame: usize) -> String {
+    format!("{}{:05}.png", prefix, frame)
+}
+
+#[test]
+fn test_frame_filename() {
+    assert_eq!(frame_filename("frames/zoom_", 7), "frames/zoom_00007.png");
+    assert_eq!(frame_filename("", 123456), "123456.png");
+}
+
+/// 以 center 为中心, 按 zooms (通常由 frame_zooms 生成) 中的缩放倍数依次渲染每一帧, 并写入编号的 PNG 文件
+///
+/// 每一帧的视口都按图像的宽高比计算, 旋转由 renderer.options.rotation 决定
+/// record 描述了整段动画的渲染参数, 图像尺寸取自 record.bounds, 写入每一帧时角点会换成这一帧自己的角点
+/// 每写入一帧就调用一次 on_frame(帧号, 文件名), 返回写入的帧数;
+/// 渲染被取消时 (见 RenderOptions::progress) 正在渲染的那一帧不完整, 不写入文件, 返回值小于 zooms.len()
+pub fn render_zoom(
+    prefix: &str,
+    record: &RenderRecord,
+    center: Complex<f64>,
+    zooms: &[f64],
+    renderer: &Renderer,
+    mut on_frame: impl FnMut(usize, &str),
+) -> Result<usize, RenderError> {
+    let bounds = record.bounds;
+    let palette = &renderer.options.palette;
+    for (frame, &zoom) in zooms.iter().enumerate() {
+        let (upper_left, lower_right) = Viewport { center, zoom, rotation: 0.0 }.corners(bounds);
+        let pixels = renderer.render_corners(bounds, upper_left, lower_right)?;
+        if renderer.options.cancelled() {
+            return Ok(frame);
+        }
+        let filename = frame_filename(prefix, frame);
+        let text = record.with_corners(upper_left, lower_right).to_text();
+        write_image(&filename, &pixels, bounds, palette.color_type(), &text).map_err(RenderError::io(&filename))?;
+        on_frame(frame, &filename);
+    }
+    Ok(zooms.len())
+}
+
+#[test]
+fn test_render_zoom_cancelled() {
+    use crate::parallel::Backend;
+    use crate::progress::Progress;
+    use crate::RenderOptions;
+    use std::sync::Arc;
+
+    let prefix = std::env::temp_dir().join(format!("zoom-cancel-test-{}-", std::process::id()));
+    let prefix = prefix.to_str().unwrap();
+    let record = RenderRecord {
+        bounds: (8, 6),
+        upper_left: String::new(),
+        lower_right: String::new(),
+        rotate: 0.0,
+        limit: 255,
+        options: String::new(),
+    };
+    let center = Complex { re: -0.5, im: 0.0 };
+    let zooms = frame_zooms(1.0, 4.0, 3);
+    let renderer = Renderer { backend: Backend::Single, threads: 1, ..Renderer::default() };
+    let mut written = Vec::new();
+    let frames = render_zoom(prefix, &record, center, &zooms, &renderer, |frame, filename| {
+        written.push((frame, filename.to_string()))
+    });
+    assert_eq!(frames.unwrap(), 3);
+    assert_eq!(written, (0..3).map(|frame| (frame, frame_filename(prefix, frame))).collect::<Vec<_>>());
+    for frame in 0..3 {
+        std::fs::remove_file(frame_filename(prefix, frame)).unwrap();
+    }
+
+    // 取消之后渲染的帧不完整, 一帧都不写入
+    let progress = Arc::new(Progress::new(record.bounds.1));
+    progress.cancel();
+    let options = RenderOptions { progress: Some(progress), ..RenderOptions::default() };
+    let renderer = Renderer { options, ..renderer };
+    let frames = render_zoom(prefix, &record, center, &zooms, &renderer, |_, _| panic!("no frame should be written"));
+    assert_eq!(frames.unwrap(), 0);
+    assert!(!std::path::Path::new(&frame_filename(prefix, 0)).exists());
+}
@@ -0,0 +1,103 @@
+//! bench 子命令: 在同一个视口上依次用每个并发后端渲染, 比较耗时并检查输出是否一致
+
+use concurrency::parallel::Backend;
+use concurrency::{RenderError, RenderOptions};
+use num::Complex;
+use std::time::{Duration, Instant};
+
+/// 一个后端的计时结果
+pub struct Timing {
+    pub backend: Backend,
+    pub threads: usize,
+    /// 每一轮渲染的耗时
+    pub runs: Vec<Duration>,
+    /// 输出是否与单线程渲染的结果完全一致
+    pub matches: bool,
+}
+
+impl Timing {
+    pub fn best(&self) -> Duration {
+        *self.runs.iter().min().unwrap()
+    }
+
+    pub fn mean(&self) -> Duration {
+        self.runs.iter().sum::<Duration>() / self.runs.len() as u32
+    }
+}
+
+/// 在同一个视口上依次用所有后端渲染 repeat 轮, 返回每个后端的计时结果
+///
+/// 第一个结果总是单线程的 render, 其余后端的输出都会和它逐字节比较; 后端渲染出错时返回错误
+pub fn run(
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    options: &RenderOptions,
+    threads: usize,
+    repeat: usize,
+) -> Result<Vec<Timing>, RenderError> {
+    assert!(repeat > 0);
+    let mut reference: Option<Vec<u8>> = None;
+    let mut timings = Vec::new();
+
+    for backend in Backend::ALL {
+        let threads = if backend == Backend::Single { 1 } else { threads };
+        let mut pixels = vec![0; bounds.0 * bounds.1 * options.palette.channels()];
+        let mut runs = Vec::new();
+        for _ in 0..repeat {
+            let start = Instant::now();
+            backend.render(&mut pixels, bounds, upper_left, lower_right, options, threads)?;
+            runs.push(start.elapsed());
+        }
+
+        let matches = match &reference {
+            None => true,
+            Some(expected) => *expected == pixels,
+        };
+        if reference.is_none() {
+            reference = Some(pixels);
+        }
+        timings.push(Timing { backend, threads, runs, matches });
+    }
+    Ok(timings)
+}
+
+/// 把计时结果打印成表格, 加速比以单线程的最好成绩为基准
+pub fn print_table(timings: &[Timing]) {
+    let baseline = timings[0].best().as_secs_f64();
+    println!(
+        "{:<10} {:>7} {:>10} {:>10} {:>8} {:>8}",
+        "backend", "threads", "best(ms)", "mean(ms)", "speedup", "matches"
+    );
+    for timing in timings {
+        println!(
+            "{:<10} {:>7} {:>10.1} {:>10.1} {:>7.2}x {:>8}",
+            timing.backend.name(),
+            timing.threads,
+            timing.best().as_secs_f64() * 1000.0,
+            timing.mean().as_secs_f64() * 1000.0,
+            baseline / timing.best().as_secs_f64(),
+            if timing.matches { "yes" } else { "NO" },
+        );
+    }
+}
+
+#[test]
+fn test_run() {
+    let timings = run(
+        (32, 24),
+        Complex { re: -2.0, im: 1.2 },
+        Complex { re: 1.0, im: -1.2 },
+        &RenderOptions::default(),
+        2,
+        2,
+    )
+    .unwrap();
+    assert_eq!(timings.len(), Backend::ALL.len());
+    assert_eq!(timings[0].backend, Backend::Single);
+    assert_eq!(timings[0].threads, 1);
+    for timing in &timings {
+        assert_eq!(timing.runs.len(), 2);
+        assert!(timing.matches);
+    }
+}
@@ -0,0 +1,616 @@
+//! 命令行参数和渲染配置文件
+//!
+//! 每个子命令的参数都在这里声明, clap 在解析时就把它们转换成对应的类型, 出错时会指出是哪一个参数
+//! 影响渲染的选项 (RenderArgs) 也可以写在 --config 指定的 TOML 文件中, 键名与命令行选项相同, 两边都给出时命令行优先
+//! 图像尺寸, 视口和输出选项每次渲染都不同, 只能在命令行上给出
+//! 每个子命令只接受它用得到的渲染选项, 见 RenderArgs::only
+
+use concurrency::error::RenderError;
+use concurrency::output::Format;
+use concurrency::palette::Palette;
+use concurrency::parallel::{self, Backend};
+use concurrency::{fractal, parse_complex, parse_pair, RenderOptions, Renderer};
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use num::Complex;
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::path::PathBuf;
+
+const EXAMPLES: &str = "\
+Examples:
+  mandelbrot mandel.png 4000x3000 -1.20,0.35 -1,0.20 --palette=fire --smooth --limit=1000
+  mandelbrot mandel.png 4000x3000 --center=-0.75,0.1 --zoom=4 --rotate=30
+  mandelbrot julia.png 4000x3000 -1.6,1.2 1.6,-1.2 --julia=-0.8,0.156
+  mandelbrot mandel.png 4000x3000 -1.20,0.35 -1,0.20 --config=render.toml
+  mandelbrot mandel.raw 4000x3000 -1.20,0.35 -1,0.20 --smooth && mandelbrot recolor mandel.png mandel.raw --palette=fire
+  mandelbrot rerender big.png mandel.png 8000x6000 --limit=5000
+  mandelbrot buddhabrot buddha.png 1000x1000 -2,1.5 1,-1.5 --orbits=20000000 --limit=2000
+  mandelbrot serve 127.0.0.1:17777 --palette=ocean --smooth --limit=2000
+  mandelbrot bench 1000x750 -1.20,0.35 -1,0.20 --threads=8
+  mandelbrot zoom frames/zoom_ 1280x720 -0.743643887,0.131825904 1 1e6 300 --smooth --limit=2000
+
+A render config file sets the render options with the same names as on the command line;
+the image size, viewport and output options are only given on the command line, e.g.
+  palette = \"fire\"
+  smooth = true
+  limit = 1000
+
+Exit status: 2 for invalid arguments, 3 for an invalid image size, 4 for an invalid viewport, 5 for I/O errors,
+6 when the rendering threads cannot be started";
+
+#[derive(Parser, Debug)]
+#[command(name = "mandelbrot", version, about = "Render the Mandelbrot set, its relatives and their Julia sets", after_help = EXAMPLES)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+impl Cli {
+    /// 解析命令行参数; 第一个参数不是子命令时默认是 render, 与原来的 `mandelbrot FILE PIXELS ...` 写法兼容
+    pub fn parse_args() -> Cli {
+        let mut args: Vec<OsString> = std::env::args_os().collect();
+        let first = args.get(1).and_then(|arg| arg.to_str()).unwrap_or("-h");
+        let command = Cli::command();
+        let builtin = ["help", "-h", "--help", "-V", "--version"];
+        if !builtin.contains(&first) && command.find_subcommand(first).is_none() {
+            args.insert(1, "render".into());
+        }
+        Cli::parse_from(args)
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    #[command(about = "Render an image (the default when no command is given)")]
+    Render(RenderCommand),
+    #[command(about = "Compare the concurrent backends on the same viewport")]
+    Bench(BenchCommand),
+    #[command(about = "Render a numbered PNG sequence zooming towards a center point")]
+    Zoom(ZoomCommand),
+    #[command(about = "Render a Buddhabrot from the orbits of random points")]
+    Buddhabrot(BuddhabrotCommand),
+    #[command(about = "Write a raw escape-value file again with another palette or format")]
+    Recolor(RecolorCommand),
+    #[command(about = "Repeat the render recorded in a PNG file, optionally at another size")]
+    Rerender(RerenderCommand),
+    #[command(about = "Serve map tiles over HTTP with a browser viewer at http://ADDRESS/")]
+    Serve(ServeCommand),
+}
+
+#[derive(Args, Debug)]
+pub struct RenderCommand {
+    #[arg(value_name = "FILE", help = "Output image file")]
+    pub file: String,
+    #[arg(value_name = "PIXELS", value_parser = dimensions, help = "Image size, e.g. 4000x3000")]
+    pub pixels: (usize, usize),
+    // 保留角点的原文, 深度缩放时需要原始的高精度写法
+    #[arg(
+        value_name = "UPPERLEFT",
+        value_parser = complex_text,
+        allow_hyphen_values = true,
+        required_unless_present = "center",
+        conflicts_with = "center",
+        help = "Upper left corner RE,IM"
+    )]
+    pub upper_left: Option<String>,
+    #[arg(
+        value_name = "LOWERRIGHT",
+        value_parser = complex_text,
+        allow_hyphen_values = true,
+        required_unless_present = "center",
+        help = "Lower right corner RE,IM"
+    )]
+    pub lower_right: Option<String>,
+    #[arg(long, value_name = "RE,IM", value_parser = complex, allow_hyphen_values = true, help = "Center of the viewport, instead of the corners")]
+    pub center: Option<Complex<f64>>,
+    #[arg(long, default_value_t = 1.0, value_parser = positive_f64, requires = "center", help = "Zoom factor around --center")]
+    pub zoom: f64,
+    #[arg(long, conflicts_with = "center", help = "Widen the corners to match the aspect ratio of the image")]
+    pub fix_aspect: bool,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    #[command(flatten)]
+    pub render: RenderArgs,
+}
+
+// 输出文件的格式和分块渲染的检查点 (这里不能用文档注释, clap 会把它当作命令的说明)
+#[derive(Args, Debug, Clone)]
+pub struct OutputArgs {
+    #[arg(
+        long,
+        value_parser = format,
+        help = "png, png16, pnm, tiff or raw [default: from the file extension]; png16 and raw store escape values"
+    )]
+    pub format: Option<Format>,
+    #[arg(long, value_name = "DIR", help = "Render tiles into DIR, resume after an interruption and stream the PNG")]
+    pub checkpoint: Option<PathBuf>,
+    #[arg(long, default_value_t = 512, value_parser = positive_usize, requires = "checkpoint", help = "Tile size for --checkpoint")]
+    pub tile_size: usize,
+}
+
+#[derive(Args, Debug)]
+pub struct BenchCommand {
+    #[arg(value_parser = dimensions)]
+    pub pixels: (usize, usize),
+    #[arg(value_name = "UPPERLEFT", value_parser = complex, allow_hyphen_values = true)]
+    pub upper_left: Complex<f64>,
+    #[arg(value_name = "LOWERRIGHT", value_parser = complex, allow_hyphen_values = true)]
+    pub lower_right: Complex<f64>,
+    #[arg(long, default_value_t = 3, value_parser = positive_usize, help = "Runs per backend, the fastest one is reported")]
+    pub repeat: usize,
+    #[command(flatten)]
+    pub render: RenderArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ZoomCommand {
+    #[arg(help = "Frames are written to PREFIX00000.png, PREFIX00001.png, ...")]
+    pub prefix: String,
+    #[arg(value_parser = dimensions)]
+    pub pixels: (usize, usize),
+    #[arg(value_name = "CENTER", value_parser = complex, allow_hyphen_values = true)]
+    pub center: Complex<f64>,
+    #[arg(value_parser = positive_f64)]
+    pub start_zoom: f64,
+    #[arg(value_parser = positive_f64)]
+    pub end_zoom: f64,
+    #[arg(value_parser = positive_usize)]
+    pub frames: usize,
+    #[command(flatten)]
+    pub render: RenderArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct BuddhabrotCommand {
+    pub file: String,
+    #[arg(value_parser = dimensions)]
+    pub pixels: (usize, usize),
+    #[arg(value_name = "UPPERLEFT", value_parser = complex, allow_hyphen_values = true)]
+    pub upper_left: Complex<f64>,
+    #[arg(value_name = "LOWERRIGHT", value_parser = complex, allow_hyphen_values = true)]
+    pub lower_right: Complex<f64>,
+    #[arg(long, default_value_t = 1_000_000, help = "Number of random points")]
+    pub orbits: usize,
+    #[arg(long, default_value_t = 0, help = "Random seed, the same seed always gives the same image")]
+    pub seed: u64,
+    #[arg(long, value_parser = format, help = "png, pnm or tiff [default: from the file extension]")]
+    pub format: Option<Format>,
+    #[command(flatten)]
+    pub render: RenderArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct RecolorCommand {
+    pub file: String,
+    #[arg(value_name = "RAW_FILE")]
+    pub raw: String,
+    #[arg(long, value_parser = format, help = "png, png16, pnm, tiff or raw [default: from the file extension]")]
+    pub format: Option<Format>,
+    // 只用到其中的调色板
+    #[command(flatten)]
+    pub render: RenderArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct RerenderCommand {
+    pub file: String,
+    #[arg(value_name = "SOURCE_PNG")]
+    pub source: String,
+    #[arg(value_parser = dimensions, help = "New image size [default: the recorded size]")]
+    pub pixels: Option<(usize, usize)>,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    #[command(flatten)]
+    pub render: RenderArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ServeCommand {
+    #[arg(help = "Address to listen on, e.g. 127.0.0.1:17777")]
+    pub address: String,
+    #[arg(long, default_value_t = 1024, value_parser = positive_usize, help = "Number of rendered tiles kept in memory")]
+    pub cache: usize,
+    #[command(flatten)]
+    pub render: RenderArgs,
+}
+
+// 影响渲染的选项, 可以在命令行上给出, 也可以写在配置文件中
+//
+// 没有给出的选项为 None, 合并之后仍然没有的选项使用 RenderOptions 的默认值
+// 开关类的选项写成 Option<bool>, 这样命令行上的 --smooth=false 可以关掉配置文件中打开的 smooth
+#[derive(Args, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RenderArgs {
+    #[arg(long, value_name = "FILE", help = "Read render options from a TOML file; options on the command line take precedence")]
+    #[serde(skip)]
+    pub config: Option<PathBuf>,
+    #[arg(long, help = "mandelbrot, burning-ship, tricorn or multibrot:D [default: mandelbrot]")]
+    pub fractal: Option<String>,
+    #[arg(long, value_name = "NAME", help = "gray, fire, ocean, hsv or a gradient file [default: gray]")]
+    pub palette: Option<String>,
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", help = "Continuous coloring")]
+    pub smooth: Option<bool>,
+    #[arg(long, help = "Iteration limit [default: 255]")]
+    pub limit: Option<usize>,
+    #[arg(long, value_name = "R", help = "Escape radius, a finite number of at least 2 [default: 2]")]
+    pub escape_radius: Option<f64>,
+    #[arg(long, value_name = "RE,IM", allow_hyphen_values = true, help = "Render the Julia set of this constant")]
+    pub julia: Option<String>,
+    #[arg(long, value_name = "DEGREES", allow_hyphen_values = true, help = "Rotate the viewport counterclockwise")]
+    pub rotate: Option<f64>,
+    #[arg(long, value_name = "N", help = "NxN supersampling per pixel [default: 1]")]
+    pub samples: Option<usize>,
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", help = "Jitter the supersampling points")]
+    pub jitter: Option<bool>,
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", help = "Use the vectorized z^2 + c kernel")]
+    pub simd: Option<bool>,
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Skip the main cardioid and period-2 bulb, detect periodic orbits"
+    )]
+    pub interior_check: Option<bool>,
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", help = "Two-pass histogram-equalized coloring")]
+    pub equalize: Option<bool>,
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Arbitrary-precision corners with perturbation, Mandelbrot only"
+    )]
+    pub deep: Option<bool>,
+    #[arg(long, help = "Number of threads [default: available parallelism]")]
+    pub threads: Option<usize>,
+    #[arg(long, help = "single, crossbeam or rayon [default: crossbeam]")]
+    pub backend: Option<String>,
+}
+
+/// 决定每个像素颜色的选项, 逐点渲染的子命令都支持; 用于 RenderArgs::only
+pub const KERNEL_OPTIONS: [&str; 10] =
+    ["fractal", "palette", "smooth", "limit", "escape-radius", "julia", "samples", "jitter", "simd", "interior-check"];
+
+/// 合并配置文件并检查过的渲染设置
+pub struct RenderSettings {
+    pub options: RenderOptions,
+    pub threads: usize,
+    pub backend: Backend,
+    /// 视口的旋转角度, 单位是度
+    pub rotate: f64,
+    pub deep: bool,
+    /// 写入渲染记录的选项, 见 RenderArgs::flags
+    pub flags: String,
+    /// 命令行或配置文件中给出了的选项, 见 RenderArgs::given
+    pub given: Vec<&'static str>,
+}
+
+impl RenderArgs {
+    /// 逐项合并, self 中已经给出的选项优先
+    pub fn or(self, other: RenderArgs) -> RenderArgs {
+        RenderArgs {
+            config: self.config.or(other.config),
+            fractal: self.fractal.or(other.fractal),
+            palette: self.palette.or(other.palette),
+            smooth: self.smooth.or(other.smooth),
+            limit: self.limit.or(other.limit),
+            escape_radius: self.escape_radius.or(other.escape_radius),
+            julia: self.julia.or(other.julia),
+            rotate: self.rotate.or(other.rotate),
+            samples: self.samples.or(other.samples),
+            jitter: self.jitter.or(other.jitter),
+            simd: self.simd.or(other.simd),
+            interior_check: self.interior_check.or(other.interior_check),
+            equalize: self.equalize.or(other.equalize),
+            deep: self.deep.or(other.deep),
+            threads: self.threads.or(other.threads),
+            backend: self.backend.or(other.backend),
+        }
+    }
+
+    /// 读入 --config 指定的配置文件并合并进来, 命令行上给出的选项优先
+    pub fn with_config(self) -> Result<RenderArgs, String> {
+        let path = match &self.config {
+            None => return Ok(self),
+            Some(path) => path.clone(),
+        };
+        let text = std::fs::read_to_string(&path).map_err(|error| format!("error reading {}: {}", path.display(), error))?;
+        let file: RenderArgs = toml::from_str(&text).map_err(|error| format!("error parsing {}: {}", path.display(), error))?;
+        Ok(RenderArgs { config: None, ..self.or(file) })
+    }
+
+    /// 合并配置文件, 并检查给出的选项是否都在 supported 中, 不支持的选项不会被悄悄忽略
+    ///
+    /// command 是子命令的名字, 用在错误信息中; 哪些选项算给出见 given
+    pub fn only(self, command: &str, supported: &[&str]) -> Result<RenderArgs, String> {
+        let args = self.with_config()?;
+        match args.given().into_iter().find(|name| !supported.contains(name)) {
+            Some(name) => Err(format!("'--{}' is not supported by the {} command", name, command)),
+            None => Ok(args),
+        }
+    }
+
+    /// 给出了的选项的名字 (不含配置文件本身), 关掉的开关 (例如 --smooth=false) 不算给出
+    pub fn given(&self) -> Vec<&'static str> {
+        let on = |switch: Option<bool>| switch == Some(true);
+        let given = [
+            ("fractal", self.fractal.is_some()),
+            ("palette", self.palette.is_some()),
+            ("smooth", on(self.smooth)),
+            ("limit", self.limit.is_some()),
+            ("escape-radius", self.escape_radius.is_some()),
+            ("julia", self.julia.is_some()),
+            ("rotate", self.rotate.is_some()),
+            ("samples", self.samples.is_some()),
+            ("jitter", on(self.jitter)),
+            ("simd", on(self.simd)),
+            ("interior-check", on(self.interior_check)),
+            ("equalize", on(self.equalize)),
+            ("deep", on(self.deep)),
+            ("threads", self.threads.is_some()),
+            ("backend", self.backend.is_some()),
+        ];
+        given.into_iter().filter(|(_, given)| *given).map(|(name, _)| name).collect()
+    }
+
+    /// 解析命令行选项的写法, 例如渲染记录中的 "--palette=fire --smooth"; 值可以像 flags 写出的那样加上双引号
+    pub fn from_flags(flags: &str) -> Result<RenderArgs, String> {
+        #[derive(Parser)]
+        struct Flags {
+            #[command(flatten)]
+            render: RenderArgs,
+        }
+        let args = std::iter::once("flags".to_string()).chain(split_flags(flags)?);
+        Flags::try_parse_from(args).map(|flags| flags.render).map_err(|error| error.to_string())
+    }
+
+    /// 写回命令行选项的写法, 按名字排序, 可以由 from_flags 原样解析回来
+    ///
+    /// 只包含影响渲染结果的选项: 线程数和并发后端不影响结果, 迭代上限和旋转角度在渲染记录中单独保存
+    /// 含有空白的值 (例如渐变文件的路径) 加上双引号, 见 quote
+    pub fn flags(&self) -> String {
+        let switch = |on: Option<bool>| if on == Some(true) { Some(String::new()) } else { None };
+        let values = [
+            ("deep", switch(self.deep)),
+            ("equalize", switch(self.equalize)),
+            ("escape-radius", self.escape_radius.map(|radius| radius.to_string())),
+            ("fractal", self.fractal.clone()),
+            ("interior-check", switch(self.interior_check)),
+            ("jitter", switch(self.jitter)),
+            ("julia", self.julia.clone()),
+            ("palette", self.palette.clone()),
+            ("samples", self.samples.map(|samples| samples.to_string())),
+            ("simd", switch(self.simd)),
+            ("smooth", switch(self.smooth)),
+        ];
+        let flags: Vec<String> = values
+            .into_iter()
+            .filter_map(|(name, value)| value.map(|value| (name, value)))
+            .map(|(name, value)| if value.is_empty() { format!("--{}", name) } else { format!("--{}={}", name, quote(&value)) })
+            .collect();
+        flags.join(" ")
+    }
+
+    /// 合并配置文件, 检查每一个选项并转换成渲染设置; 错误信息指出是哪一个选项
+    ///
+    /// 渐变文件的路径换成规范的绝对路径, 渲染记录从其他目录重新渲染时也能找到它
+    pub fn resolve(self) -> Result<RenderSettings, String> {
+        let mut args = self.with_config()?;
+        let invalid = |name: &str, value: &dyn Display, reason: &dyn Display| {
+            format!("invalid value '{}' for '--{}': {}", value, name, reason)
+        };
+
+        let mut options = RenderOptions::default();
+        if let Some(name) = &args.fractal {
+            options.fractal = fractal::from_name(name)
+                .ok_or_else(|| invalid("fractal", name, &"expected mandelbrot, burning-ship, tricorn or multibrot:D"))?;
+        }
+        if let Some(name) = args.palette.clone() {
+            options.palette = Palette::from_name(&name).map_err(|error| invalid("palette", &name, &error))?;
+            if matches!(options.palette, Palette::Gradient(_)) {
+                let path = std::fs::canonicalize(&name).map_err(|error| invalid("palette", &name, &error))?;
+                args.palette = Some(path.to_string_lossy().into_owned());
+            }
+        }
+        options.smooth = args.smooth.unwrap_or(false);
+        options.limit = args.limit.unwrap_or(options.limit);
+        options.escape_radius = args.escape_radius.unwrap_or(options.escape_radius);
+        if let Some(c) = &args.julia {
+            options.julia = Some(complex(c).map_err(|error| invalid("julia", c, &error))?);
+        }
+        options.samples = args.samples.unwrap_or(options.samples);
+        options.jitter = args.jitter.unwrap_or(false);
+        options.simd = args.simd.unwrap_or(false);
+        options.interior_check = args.interior_check.unwrap_or(false);
+        options.equalize = args.equalize.unwrap_or(false);
+
+        let threads = args.threads.unwrap_or_else(parallel::default_threads);
+        let backend = match &args.backend {
+            None => Backend::Crossbeam,
+            Some(name) => Backend::from_name(name).ok_or_else(|| invalid("backend", name, &"expected single, crossbeam or rayon"))?,
+        };
+        let rotate = args.rotate.unwrap_or(0.0);
+        if !rotate.is_finite() {
+            return Err(invalid("rotate", &rotate, &"must be finite"));
+        }
+
+        // 数值范围和选项组合由库检查, 这里只把字段名换成对应的命令行选项
+        let renderer = Renderer { options, backend, threads };
+        renderer.check().map_err(|error| match error {
+            RenderError::Options { option: "equalize", .. } => {
+                "'--equalize' cannot be used with '--samples' or '--jitter'".to_string()
+            }
+            RenderError::Options { option, value, reason } => invalid(&option.replace('_', "-"), &value, &reason),
+            other => other.to_string(),
+        })?;
+        let Renderer { options, backend, threads } = renderer;
+        Ok(RenderSettings { options, threads, backend, rotate, deep: args.deep.unwrap_or(false), flags: args.flags(), given: args.given() })
+    }
+}
+
+/// 值中含有空白, 双引号或反斜杠时加上双引号, 其中的 " 和 \ 前面再加上 \; 可以由 split_flags 还原
+fn quote(value: &str) -> String {
+    if !value.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        return value.to_string();
+    }
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// 按空白切分 flags 写出的选项, 双引号中的空白不切分, 双引号中的 \ 转义下一个字符
+fn split_flags(flags: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quoted = false;
+    let mut chars = flags.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                word.get_or_insert_with(String::new);
+            }
+            '\\' if quoted => match chars.next() {
+                Some(escaped) => word.get_or_insert_with(String::new).push(escaped),
+                None => break,
+            },
+            c if c.is_whitespace() && !quoted => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quoted {
+        return Err(format!("unterminated quote in '{}'", flags));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[test]
+fn test_render_args() {
+    let parse = |args: &[&str]| Cli::try_parse_from(std::iter::once("mandelbrot").chain(args.iter().copied()));
+    let command = match parse(&["render", "a.png", "10x10", "-1,1", "1,-1", "--palette=fire", "--smooth", "--limit=100"]) {
+        Ok(Cli { command: Command::Render(command) }) => command,
+        other => panic!("{:?}", other),
+    };
+    assert_eq!(command.pixels, (10, 10));
+    assert_eq!(command.upper_left.as_deref(), Some("-1,1"));
+    assert_eq!(command.render.smooth, Some(true));
+    assert_eq!(command.render.limit, Some(100));
+
+    // 写回的选项可以原样解析回来, 迭代上限单独记录
+    assert_eq!(command.render.flags(), "--palette=fire --smooth");
+    let parsed = RenderArgs::from_flags(&command.render.flags()).unwrap();
+    assert_eq!(parsed, RenderArgs { limit: None, ..command.render });
+
+    // 含有空格和引号的渐变文件路径加上引号, 同样可以解析回来
+    let render = RenderArgs { palette: Some("my gradients/a\"b\\.txt".to_string()), smooth: Some(true), ..RenderArgs::default() };
+    assert_eq!(render.flags(), "--palette=\"my gradients/a\\\"b\\\\.txt\" --smooth");
+    assert_eq!(RenderArgs::from_flags(&render.flags()).unwrap(), render);
+    assert!(RenderArgs::from_flags("--palette=\"a b").unwrap_err().contains("unterminated"));
+
+    // 格式错误时指出是哪一个参数
+    let error = parse(&["render", "a.png", "10by10", "-1,1", "1,-1"]).unwrap_err();
+    assert!(error.to_string().contains("PIXELS"), "{}", error);
+    let error = parse(&["render", "a.png", "10x10", "-1,1", "1,-1", "--limit=many"]).unwrap_err();
+    assert!(error.to_string().contains("--limit"), "{}", error);
+    assert!(parse(&["render", "a.png", "10x10", "--center=-0.5,0", "--zoom=2"]).is_ok());
+    assert!(parse(&["render", "a.png", "10x10", "-1,1"]).is_err());
+    assert!(parse(&["zoom", "f_", "10x10", "-0.5,0", "1", "0", "10"]).is_err());
+}
+
+#[test]
+fn test_render_config() {
+    let path = std::env::temp_dir().join(format!("render-config-test-{}.toml", std::process::id()));
+    std::fs::write(&path, "palette = \"fire\"\nsmooth = true\nlimit = 1000\ninterior-check = true\n").unwrap();
+    let cli = RenderArgs {
+        config: Some(path.clone()),
+        limit: Some(50),
+        smooth: Some(false),
+        ..RenderArgs::default()
+    };
+    // 命令行上的选项优先, 包括关掉配置文件中打开的开关
+    let settings = cli.clone().resolve().unwrap();
+    assert_eq!((settings.options.limit, settings.options.smooth), (50, false));
+    assert!(settings.options.interior_check);
+    assert_eq!(settings.flags, "--interior-check --palette=fire");
+    assert_eq!(settings.given, ["palette", "limit", "interior-check"]);
+
+    // 子命令不支持的选项, 无论来自命令行还是配置文件, 都报告出来
+    assert!(cli.clone().only("render", &KERNEL_OPTIONS).is_ok());
+    let error = cli.clone().only("buddhabrot", &["palette", "limit", "threads"]).unwrap_err();
+    assert_eq!(error, "'--interior-check' is not supported by the buddhabrot command");
+    let error = RenderArgs { smooth: None, ..cli.clone() }.only("buddhabrot", &["palette", "limit"]).unwrap_err();
+    assert_eq!(error, "'--smooth' is not supported by the buddhabrot command");
+
+    std::fs::write(&path, "limit = 0\n").unwrap();
+    assert_eq!(cli.clone().resolve().unwrap().options.limit, 50);
+    let cli = RenderArgs { limit: None, ..cli };
+    assert_eq!(cli.clone().resolve().err().unwrap(), "invalid value '0' for '--limit': must be positive");
+    std::fs::write(&path, "colour = \"red\"\n").unwrap();
+    assert!(cli.resolve().err().unwrap().contains("colour"));
+    std::fs::remove_file(&path).unwrap();
+
+    // 渐变文件记录为规范路径
+    let gradient = std::env::temp_dir().join(format!("render-gradient-test-{}.txt", std::process::id()));
+    std::fs::write(&gradient, "0 0 0 0\n1 255 255 255\n").unwrap();
+    let indirect = gradient.parent().unwrap().join(".").join(gradient.file_name().unwrap());
+    let cli = RenderArgs { palette: Some(indirect.to_str().unwrap().to_string()), ..RenderArgs::default() };
+    let canonical = std::fs::canonicalize(&gradient).unwrap();
+    assert_eq!(cli.resolve().unwrap().flags, format!("--palette={}", canonical.display()));
+    std::fs::remove_file(&gradient).unwrap();
+}
+
+/// 以 clap 的格式报告子命令 subcommand 的参数错误并退出, 错误信息后面附上这个子命令的用法
+pub fn exit_with(subcommand: &str, message: impl Display) -> ! {
+    let mut command = Cli::command();
+    // build 之后子命令才有完整的名字, 用法中显示为 "mandelbrot render ..."
+    command.build();
+    let mut subcommand = command.find_subcommand(subcommand).expect("unknown subcommand").clone();
+    subcommand.error(ErrorKind::ValueValidation, message).exit()
+}
+
+/// --format 给出的格式, 没有给出时根据文件的扩展名选择
+pub fn output_format(filename: &str, format: Option<Format>) -> Result<Format, String> {
+    match format {
+        Some(format) => Ok(format),
+        None => Format::from_filename(filename)
+            .ok_or_else(|| format!("cannot tell the output format from the extension of '{}', pass '--format'", filename)),
+    }
+}
+
+fn dimensions(text: &str) -> Result<(usize, usize), RenderError> {
+    parse_pair(text, 'x')
+}
+
+fn complex(text: &str) -> Result<Complex<f64>, RenderError> {
+    parse_complex(text)
+}
+
+/// 检查是否是合法的复数, 但保留原文
+fn complex_text(text: &str) -> Result<String, RenderError> {
+    complex(text).map(|_| text.to_string())
+}
+
+fn positive_usize(text: &str) -> Result<usize, String> {
+    match text.parse() {
+        Ok(0) => Err("must be positive".to_string()),
+        Ok(value) => Ok(value),
+        Err(error) => Err(format!("{}", error)),
+    }
+}
+
+fn positive_f64(text: &str) -> Result<f64, String> {
+    match text.parse::<f64>() {
+        Ok(value) if value > 0.0 && value.is_finite() => Ok(value),
+        Ok(_) => Err("must be positive".to_string()),
+        Err(error) => Err(format!("{}", error)),
+    }
+}
+
+fn format(text: &str) -> Result<Format, String> {
+    Format::from_name(text).ok_or_else(|| {
+        let names: Vec<_> = Format::ALL.iter().map(Format::name).collect();
+        format!("expected one of {}", names.join(", "))
+    })
+}
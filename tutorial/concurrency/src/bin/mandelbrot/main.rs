@@ -0,0 +1,373 @@
+//! 命令行程序: 解析参数, 检查组合是否合法, 再调用 concurrency 库完成渲染和输出
+//!
+//! cargo run --release --bin mandelbrot -- mandel.png 4000x3000 -1.20,0.35 -1,0.20
+
+use concurrency::viewport::{self, Rotation};
+use concurrency::{animation, buddhabrot, deep, metadata, output, progress, server, tiles};
+use concurrency::{parse_complex, render_escapes, RenderError, RenderOptions, Renderer, Viewport};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+mod bench;
+mod cli;
+
+
+/// bench 子命令: 在同一个视口上比较各个并发后端的耗时
+fn run_bench(command: cli::BenchCommand) -> Result<(), RenderError> {
+    // 所有的后端都会比较一遍, 所以不接受 --backend
+    let supported = [&cli::KERNEL_OPTIONS[..], &["equalize", "threads"]].concat();
+    let settings = command
+        .render
+        .only("bench", &supported)
+        .and_then(cli::RenderArgs::resolve)
+        .unwrap_or_else(|error| cli::exit_with("bench", error));
+    viewport::check_bounds(command.pixels)?;
+    viewport::check_corners(command.pixels, command.upper_left, command.lower_right)?;
+    let timings = bench::run(
+        command.pixels,
+        command.upper_left,
+        command.lower_right,
+        &settings.options,
+        settings.threads,
+        command.repeat,
+    )?;
+    bench::print_table(&timings);
+    Ok(())
+}
+
+/// zoom 子命令: 朝着一个中心点逐帧放大, 输出编号的 PNG 序列
+fn run_zoom(command: cli::ZoomCommand) -> Result<(), RenderError> {
+    let supported = [&cli::KERNEL_OPTIONS[..], &["equalize", "rotate", "threads", "backend"]].concat();
+    let settings = command
+        .render
+        .only("zoom", &supported)
+        .and_then(cli::RenderArgs::resolve)
+        .unwrap_or_else(|error| cli::exit_with("zoom", error));
+    let bounds = command.pixels;
+    viewport::check_bounds(bounds)?;
+    // 放大倍数最大的一帧视口最小, 只要它能用 f64 渲染, 其他帧也都可以
+    let deepest = Viewport { center: command.center, zoom: command.start_zoom.max(command.end_zoom), rotation: 0.0 };
+    deepest.check(bounds)?;
+    let mut render_options = settings.options;
+    render_options.rotation = Viewport { center: command.center, zoom: 1.0, rotation: settings.rotate }.rotation();
+    // 每一帧都会输出一行信息, 所以这里只用 Progress 实现 Ctrl-C 取消, 不启动报告线程
+    let progress = Arc::new(progress::Progress::new(command.frames * bounds.1));
+    cancel_on_ctrlc(&progress);
+    render_options.progress = Some(progress);
+
+    // 每一帧的角点由 render_zoom 填入
+    let record = render_record(bounds, (String::new(), String::new()), settings.rotate, &render_options, &settings.flags);
+    let zooms = animation::frame_zooms(command.start_zoom, command.end_zoom, command.frames);
+    let renderer = Renderer { options: render_options, backend: settings.backend, threads: settings.threads };
+    let written = animation::render_zoom(&command.prefix, &record, command.center, &zooms, &renderer, |frame, filename| {
+        eprintln!("frame {}/{}: zoom {:.6e} -> {}", frame + 1, zooms.len(), zooms[frame], filename);
+    })?;
+    if written < zooms.len() {
+        eprintln!("cancelled during frame {}, not written", written + 1);
+    }
+    Ok(())
+}
+
+/// 写入图像的渲染记录, flags 是 RenderArgs::flags 给出的影响渲染结果的选项
+///
+/// 视口总是记录成最终使用的两个角点, 所以 --center, --zoom 和 --fix-aspect 都不需要保留
+fn render_record(
+    bounds: (usize, usize),
+    corners: (String, String),
+    rotate: f64,
+    render_options: &RenderOptions,
+    flags: &str,
+) -> metadata::RenderRecord {
+    metadata::RenderRecord {
+        bounds,
+        upper_left: corners.0,
+        lower_right: corners.1,
+        rotate,
+        limit: render_options.limit,
+        options: flags.to_string(),
+    }
+}
+
+/// 开始在标准错误输出上显示进度, 并让 Ctrl-C 取消这次渲染; total 是要渲染的总行数
+fn start_progress(render_options: &mut RenderOptions, total: usize) -> JoinHandle<()> {
+    let progress = Arc::new(progress::Progress::new(total));
+    cancel_on_ctrlc(&progress);
+    let reporter = progress.spawn_reporter(Duration::from_millis(250));
+    render_options.progress = Some(progress);
+    reporter
+}
+
+/// 按下 Ctrl-C 时取消渲染; 无法安装处理函数时只给出警告, Ctrl-C 会直接终止进程
+fn cancel_on_ctrlc(progress: &Arc<progress::Progress>) {
+    if let Err(error) = progress::cancel_on_ctrlc(progress) {
+        eprintln!("warning: Ctrl-C will not cancel the render: {}", error);
+    }
+}
+
+/// 停止显示进度, 渲染被取消时返回 true
+fn finish_progress(render_options: &RenderOptions, reporter: JoinHandle<()>) -> bool {
+    let progress = render_options.progress.as_ref().expect("progress was not started");
+    progress.finish(reporter);
+    progress.is_cancelled()
+}
+
+/// subcommand 是报告参数错误时显示用法的子命令, rerender 也通过这里渲染
+fn run_render(subcommand: &str, command: cli::RenderCommand) -> Result<(), RenderError> {
+    let settings = command.render.resolve().unwrap_or_else(|error| cli::exit_with(subcommand, error));
+    let format = cli::output_format(&command.file, command.output.format).unwrap_or_else(|error| cli::exit_with(subcommand, error));
+    let bounds = command.pixels;
+    viewport::check_bounds(bounds)?;
+    settings.options.check_row(bounds.0)?;
+    let (threads, backend, rotation) = (settings.threads, settings.backend, settings.rotate);
+    let mut render_options = settings.options;
+
+    // 视口可以用两个角点给出, 也可以用 --center 和 --zoom 给出
+    let (upper_left, lower_right) = match command.center {
+        Some(center) => {
+            let viewport = Viewport { center, zoom: command.zoom, rotation };
+            render_options.rotation = viewport.rotation();
+            // 角点是算出来的, 错误信息要指向用户实际给出的选项
+            viewport.check(bounds).map_err(|error| match error {
+                RenderError::Viewport { reason } => RenderError::Viewport { reason: format!("{} (from '--center' and '--zoom')", reason) },
+                other => other,
+            })?
+        }
+        None => {
+            // 没有 --center 时 clap 保证两个角点都已给出
+            let upper_left = parse_complex(command.upper_left.as_deref().unwrap())?;
+            let lower_right = parse_complex(command.lower_right.as_deref().unwrap())?;
+            // 深度缩放的视口可以小于 f64 的精度, 截断成 f64 的角点可能重合, 视口和宽高比都无从检查
+            // render_deep 重新解析原始的角点, 所以也不能按 --fix-aspect 调整
+            if settings.deep && command.fix_aspect {
+                cli::exit_with(subcommand, "'--deep' cannot be used with '--fix-aspect'");
+            }
+            if !settings.deep {
+                viewport::check_corners(bounds, upper_left, lower_right)?;
+            }
+            // 角点围成的矩形与图像的宽高比不一致时, 图像会被拉伸
+            let distortion = viewport::aspect_distortion(bounds, upper_left, lower_right);
+            let corners = if settings.deep || (distortion - 1.0).abs() <= 0.01 {
+                (upper_left, lower_right)
+            } else if command.fix_aspect {
+                viewport::fix_aspect(bounds, upper_left, lower_right)
+            } else {
+                eprintln!(
+                    "warning: the corners have {:.3} times the aspect ratio of the image, which will be stretched; pass --fix-aspect to correct it",
+                    distortion
+                );
+                (upper_left, lower_right)
+            };
+            if rotation != 0.0 {
+                render_options.rotation = Some(Rotation::new((corners.0 + corners.1) / 2.0, rotation));
+            }
+            corners
+        }
+    };
+    // 深度缩放时保留角点原始的高精度写法, 否则记录实际使用的 f64 角点
+    let corners = if settings.deep && command.center.is_none() {
+        (command.upper_left.clone().unwrap(), command.lower_right.clone().unwrap())
+    } else {
+        (format!("{},{}", upper_left.re, upper_left.im), format!("{},{}", lower_right.re, lower_right.im))
+    };
+    let record = render_record(bounds, corners, rotation, &render_options, &settings.flags);
+    let text = record.to_text();
+
+    if let Some(dir) = &command.output.checkpoint {
+        // 分块渲染: 不分配整幅图像的缓冲区, 已完成的块保存在检查点目录中, 中断后可以继续
+        if settings.deep {
+            cli::exit_with(subcommand, "'--deep' cannot be used with '--checkpoint'");
+        }
+        if format != output::Format::Png {
+            cli::exit_with(subcommand, "'--checkpoint' only writes 8-bit PNG files");
+        }
+        if render_options.equalize {
+            cli::exit_with(subcommand, "'--equalize' needs the whole image and cannot be used with '--checkpoint'");
+        }
+        let tiling = tiles::Tiling { bounds, tile_size: command.output.tile_size };
+        let channels = render_options.palette.channels();
+        // 检查点中的块必须属于同一次渲染, 渲染记录正好描述了影响渲染结果的全部参数
+        let description: Vec<String> = text.iter().map(|(keyword, value)| format!("{}: {}", keyword, value)).collect();
+        let checkpoint = tiles::Checkpoint::open(dir, tiling, channels, &description.join("\n"))
+            .map_err(RenderError::io(dir.display()))?;
+        // 每一列块都要渲染 bounds.1 行
+        let reporter = start_progress(&mut render_options, tiling.grid().0 * bounds.1);
+        let result = checkpoint.render_missing(upper_left, lower_right, backend, &render_options, threads);
+        let cancelled = finish_progress(&render_options, reporter);
+        result?;
+        if cancelled {
+            eprintln!(
+                "cancelled; finished tiles are kept in {}, run the same command again to resume",
+                dir.display()
+            );
+            return Ok(());
+        }
+        checkpoint.write_png(&command.file, &text).map_err(RenderError::io(&command.file))?;
+        return checkpoint.remove().map_err(RenderError::io(dir.display()));
+    }
+
+    if format.stores_escapes() {
+        // 16 位 PNG 和原始逃逸值文件保存的是每个像素的逃逸值, 而不是着色后的像素
+        if settings.deep {
+            cli::exit_with(subcommand, format!("'--deep' does not support the {} format", format.name()));
+        }
+        if render_options.equalize {
+            cli::exit_with(subcommand, format!("the {} format stores escape values and cannot be used with '--equalize'", format.name()));
+        }
+        if render_options.samples != 1 || render_options.jitter {
+            cli::exit_with(subcommand, format!("the {} format cannot be used with '--samples' or '--jitter'", format.name()));
+        }
+        // 逃逸值不经过调色板, render_escapes 也只有一种并发实现, 这两个选项都不起作用
+        if let Some(name) = settings.given.iter().find(|name| ["palette", "backend"].contains(name)) {
+            cli::exit_with(subcommand, format!("the {} format stores escape values and cannot be used with '--{}'", format.name(), name));
+        }
+        let reporter = start_progress(&mut render_options, bounds.1);
+        let map = render_escapes(bounds, upper_left, lower_right, &render_options, threads);
+        if finish_progress(&render_options, reporter) {
+            eprintln!("cancelled, writing the partially rendered image");
+        }
+        return output::write_escapes(&command.file, format, &map, &render_options.palette, &text)
+            .map_err(RenderError::io(&command.file));
+    }
+
+    let deep_corners = if settings.deep {
+        // 深度缩放: 重新把角点解析成任意精度的小数, 避免被截断成 f64
+        if render_options.julia.is_some() || !render_options.fractal.is_quadratic() {
+            cli::exit_with(subcommand, "'--deep' only supports the Mandelbrot set");
+        }
+        if command.center.is_some() || render_options.rotation.is_some() {
+            cli::exit_with(subcommand, "'--deep' needs explicit corners and cannot be used with '--rotate'");
+        }
+        if render_options.samples != 1 || render_options.jitter {
+            cli::exit_with(subcommand, "'--deep' cannot be used with '--samples' or '--jitter'");
+        }
+        if render_options.equalize {
+            cli::exit_with(subcommand, "'--deep' cannot be used with '--equalize'");
+        }
+        // 深度缩放有自己的渲染内核和线程划分, 这些选项对它不起作用
+        if let Some(name) = settings.given.iter().find(|name| ["simd", "interior-check", "backend"].contains(name)) {
+            cli::exit_with(subcommand, format!("'--deep' cannot be used with '--{}'", name));
+        }
+        let upper_left = deep::parse_big_complex(command.upper_left.as_deref().unwrap())?;
+        let lower_right = deep::parse_big_complex(command.lower_right.as_deref().unwrap())?;
+        deep::check_corners(&upper_left, &lower_right)?;
+        Some((upper_left, lower_right))
+    } else {
+        None
+    };
+    let mut pixels = vec![0; bounds.0 * bounds.1 * render_options.palette.channels()];
+    let reporter = start_progress(&mut render_options, bounds.1);
+    let result = match &deep_corners {
+        Some((upper_left, lower_right)) => {
+            deep::render_deep(&mut pixels, bounds, upper_left, lower_right, &render_options, threads);
+            Ok(())
+        }
+        // 单线程的 render 以及 crossbeam, rayon 两种并发实现见 parallel 模块
+        None => backend.render(&mut pixels, bounds, upper_left, lower_right, &render_options, threads),
+    };
+    let cancelled = finish_progress(&render_options, reporter);
+    result?;
+    if cancelled {
+        eprintln!("cancelled, writing the partially rendered image");
+    }
+
+    output::write_pixels(&command.file, format, &pixels, bounds, &render_options.palette, &text).map_err(RenderError::io(&command.file))
+}
+
+/// 用另一个调色板或格式重新输出原始逃逸值文件, 不需要重新计算
+fn run_recolor(command: cli::RecolorCommand) -> Result<(), RenderError> {
+    // 逃逸值已经算好了, 只能换调色板
+    let palette = command
+        .render
+        .only("recolor", &["palette"])
+        .and_then(cli::RenderArgs::resolve)
+        .unwrap_or_else(|error| cli::exit_with("recolor", error))
+        .options
+        .palette;
+    let format = cli::output_format(&command.file, command.format).unwrap_or_else(|error| cli::exit_with("recolor", error));
+    let map = output::read_raw(&command.raw).map_err(RenderError::io(&command.raw))?;
+    output::write_escapes(&command.file, format, &map, &palette, &[]).map_err(RenderError::io(&command.file))
+}
+
+/// 从 PNG 的 tEXt 块中读回渲染参数并重新渲染, 可以换一个图像尺寸
+///
+/// 命令行和配置文件中给出的选项会覆盖记录中的同名选项
+fn run_rerender(command: cli::RerenderCommand) -> Result<(), RenderError> {
+    let text = metadata::read_png_text(&command.source).map_err(RenderError::io(&command.source))?;
+    let invalid = |message: String| RenderError::io(&command.source)(std::io::Error::new(std::io::ErrorKind::InvalidData, message));
+    let record = metadata::RenderRecord::from_text(&text).ok_or_else(|| invalid("the image carries no render parameters".to_string()))?;
+
+    let mut recorded = cli::RenderArgs::from_flags(&record.options)
+        .map_err(|error| invalid(format!("invalid recorded options {:?}: {}", record.options, error)))?;
+    recorded.limit = Some(record.limit);
+    if record.rotate != 0.0 {
+        recorded.rotate = Some(record.rotate);
+    }
+    let render = command.render.with_config().unwrap_or_else(|error| cli::exit_with("rerender", error)).or(recorded);
+
+    run_render("rerender", cli::RenderCommand {
+        file: command.file,
+        pixels: command.pixels.unwrap_or(record.bounds),
+        upper_left: Some(record.upper_left),
+        lower_right: Some(record.lower_right),
+        center: None,
+        zoom: 1.0,
+        fix_aspect: false,
+        output: command.output,
+        render,
+    })
+}
+
+/// 布罗特佛: 追踪随机选取的 c 的逃逸轨道, 按轨道经过每个像素的次数着色
+fn run_buddhabrot(command: cli::BuddhabrotCommand) -> Result<(), RenderError> {
+    // 布罗特佛只追踪曼德博集的轨道, 按经过的次数着色
+    let settings = command
+        .render
+        .only("buddhabrot", &["palette", "limit", "threads"])
+        .and_then(cli::RenderArgs::resolve)
+        .unwrap_or_else(|error| cli::exit_with("buddhabrot", error));
+    viewport::check_bounds(command.pixels)?;
+    viewport::check_corners(command.pixels, command.upper_left, command.lower_right)?;
+    let format = cli::output_format(&command.file, command.format).unwrap_or_else(|error| cli::exit_with("buddhabrot", error));
+    let orbit_options = buddhabrot::OrbitOptions {
+        orbits: command.orbits,
+        limit: settings.options.limit,
+        seed: command.seed,
+    };
+
+    let bounds = command.pixels;
+    let counts = buddhabrot::accumulate(bounds, command.upper_left, command.lower_right, &orbit_options, settings.threads);
+    let pixels = buddhabrot::paint(&counts, &settings.options.palette);
+    output::write_pixels(&command.file, format, &pixels, bounds, &settings.options.palette, &[]).map_err(RenderError::io(&command.file))
+}
+
+fn run_serve(command: cli::ServeCommand) -> Result<(), RenderError> {
+    // 瓦片在阻塞线程池中单线程渲染, threads 是处理请求的线程数
+    // 每块瓦片单独着色, 均衡化会让相邻的瓦片颜色不一致; 旋转和深度缩放也不支持
+    let supported = [&cli::KERNEL_OPTIONS[..], &["threads"]].concat();
+    let settings = command
+        .render
+        .only("serve", &supported)
+        .and_then(cli::RenderArgs::resolve)
+        .unwrap_or_else(|error| cli::exit_with("serve", error));
+    let server = server::TileServer::new(settings.options, command.cache)?;
+    println!("serving on http://{}/", command.address);
+    server::serve(&command.address, server, settings.threads).map_err(RenderError::io(&command.address))
+}
+
+/// 出错时输出错误信息, 并以 RenderError::exit_code 给出的退出码退出
+fn main() {
+    let result = match cli::Cli::parse_args().command {
+        cli::Command::Render(command) => run_render("render", command),
+        cli::Command::Bench(command) => run_bench(command),
+        cli::Command::Zoom(command) => run_zoom(command),
+        cli::Command::Buddhabrot(command) => run_buddhabrot(command),
+        cli::Command::Recolor(command) => run_recolor(command),
+        cli::Command::Rerender(command) => run_rerender(command),
+        cli::Command::Serve(command) => run_serve(command),
+    };
+    if let Err(error) = result {
+        eprintln!("error: {}", error);
+        std::process::exit(error.exit_code());
+    }
+}
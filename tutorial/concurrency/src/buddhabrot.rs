@@ -0,0 +1,135 @@
+//! 布罗特佛 (Buddhabrot): 轨道密度渲染
+//!
+//! 与逃逸时间算法相反, 这里随机选取大量的 c, 追踪其中会逃逸的轨道 z = z * z + c, 统计轨道经过每个像素的次数
+//! 一条轨道可能落在图像的任何位置, 不同线程会写入同一个像素, 所以不能像 render_rows_parallel 那样把缓冲区切成互不重叠的行;
+//! 这里每个线程都累加到自己的一整块计数缓冲区中, 全部完成后再把它们加起来
+
+use crate::interior::in_cardioid_or_bulb;
+use crate::palette::Palette;
+use crate::point_to_pixel;
+use crate::supersample::unit;
+use num::Complex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct OrbitOptions {
+    /// 随机选取的 c 的个数
+    pub orbits: usize,
+    /// 最大迭代次数, 超过这个次数仍未逃逸的轨道不计入
+    pub limit: usize,
+    /// 随机数种子, 同样的种子总是得到同样的图像
+    pub seed: u64,
+}
+
+/// 每一批随机选取的 c 的个数, 线程每次领取一批
+const BATCH: usize = 4096;
+
+/// 从 z = 0 开始迭代, 把逃逸之前经过的点依次写入 orbit, 逃逸时返回 true
+fn trace(c: Complex<f64>, limit: usize, orbit: &mut Vec<Complex<f64>>) -> bool {
+    orbit.clear();
+    // 主心形和周期 2 圆盘内的轨道永远不会逃逸
+    if in_cardioid_or_bulb(c) {
+        return false;
+    }
+    let mut z = Complex { re: 0.0, im: 0.0 };
+    for _ in 0..limit {
+        z = z * z + c;
+        if z.norm_sqr() > 4.0 {
+            return true;
+        }
+        orbit.push(z);
+    }
+    false
+}
+
+/// 用 threads 个线程统计轨道经过图像中每个像素的次数
+///
+/// c 在 [-2, 2] x [-2, 2] 内均匀选取, 因为图像外的 c 的轨道也可能经过图像
+/// 每一批 c 都由种子和批号决定, 加法又满足交换律, 所以结果与线程数和调度顺序无关
+pub fn accumulate(
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    options: &OrbitOptions,
+    threads: usize,
+) -> Vec<u32> {
+    assert!(threads > 0);
+    let batches = options.orbits.div_ceil(BATCH);
+    let next_batch = AtomicUsize::new(0);
+
+    crossbeam::scope(|spawner| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                spawner.spawn(|_| {
+                    let mut counts = vec![0u32; bounds.0 * bounds.1];
+                    // 绝大多数逃逸的轨道都很短, 不按迭代上限预先分配, 否则很大的 limit 会在开始之前就耗尽内存
+                    let mut orbit = Vec::with_capacity(options.limit.min(1 << 16));
+                    loop {
+                        let batch = next_batch.fetch_add(1, Ordering::Relaxed);
+                        if batch >= batches {
+                            break;
+                        }
+                        let mut state = options.seed ^ (batch as u64).wrapping_mul(0xD1B5_4A32_D192_ED03);
+                        for _ in 0..BATCH.min(options.orbits - batch * BATCH) {
+                            let c = Complex { re: 4.0 * unit(&mut state) - 2.0, im: 4.0 * unit(&mut state) - 2.0 };
+                            if !trace(c, options.limit, &mut orbit) {
+                                continue;
+                            }
+                            for &z in &orbit {
+                                if let Some((column, row)) = point_to_pixel(bounds, z, upper_left, lower_right) {
+                                    let count = &mut counts[row * bounds.0 + column];
+                                    *count = count.saturating_add(1);
+                                }
+                            }
+                        }
+                    }
+                    counts
+                })
+            })
+            .collect();
+
+        // 把每个线程的缓冲区加到第一个线程的缓冲区上; 计数到 u32::MAX 就不再增加, 而不是溢出
+        let mut handles = handles.into_iter();
+        let mut total = handles.next().unwrap().join().unwrap();
+        for handle in handles {
+            for (sum, count) in total.iter_mut().zip(handle.join().unwrap()) {
+                *sum = sum.saturating_add(count);
+            }
+        }
+        total
+    })
+    .unwrap()
+}
+
+#[test]
+fn test_accumulate() {
+    let bounds = (60, 40);
+    let upper_left = Complex { re: -2.0, im: 1.2 };
+    let lower_right = Complex { re: 1.0, im: -1.2 };
+    let options = OrbitOptions { orbits: 20_000, limit: 200, seed: 7 };
+    let counts = accumulate(bounds, upper_left, lower_right, &options, 1);
+    assert!(counts.iter().any(|&count| count > 0));
+    assert_eq!(accumulate(bounds, upper_left, lower_right, &options, 4), counts);
+    assert_ne!(accumulate(bounds, upper_left, lower_right, &OrbitOptions { seed: 8, ..options }, 4), counts);
+
+    // 逃逸的轨道不会离开半径为 2 的圆盘, 远处的视口中没有任何计数
+    let far = accumulate(bounds, Complex { re: 3.0, im: 1.0 }, Complex { re: 4.0, im: 0.0 }, &options, 2);
+    assert!(far.iter().all(|&count| count == 0));
+}
+
+/// 按计数着色: 没有轨道经过的像素为黑色, 其余的像素按计数与最大计数之比的平方根着色,
+/// 计数越大, t 越小, 在灰度调色板上越亮
+pub fn paint(counts: &[u32], palette: &Palette) -> Vec<u8> {
+    let max = counts.iter().copied().max().unwrap_or(0).max(1) as f64;
+    let channels = palette.channels();
+    let mut pixels = vec![0; counts.len() * channels];
+    for (pixel, &count) in pixels.chunks_mut(channels).zip(counts) {
+        let t = if count == 0 { None } else { Some(1.0 - (count as f64 / max).sqrt()) };
+        palette.paint(pixel, t);
+    }
+    pixels
+}
+
+#[test]
+fn test_paint() {
+    assert_eq!(paint(&[0, 1, 4, 16], &Palette::Gray), [0, 64, 127, 255]);
+}
@@ -0,0 +1,395 @@
+//! 深度缩放: 任意精度的坐标加上微扰理论 (perturbation theory)
+//!
+//! f64 只有 53 位尾数, 视口宽度小于 1e-13 左右时相邻像素会映射到同一个复数, 图像就变成了色块
+//! 这里只用高精度算出视口中心的一条参考轨道 Z_n, 其他像素 c = C + δc 的轨道写成 z_n = Z_n + δz_n,
+//! 由 z_{n+1} = z_n^2 + c 可得 δz_{n+1} = (2 Z_n + δz_n) δz_n + δc, δz 和 δc 都很小, 用 f64 就能精确表示
+
+use crate::parallel::render_rows_parallel;
+use crate::error::RenderError;
+use crate::{parse_pair, smooth_escape, RenderOptions};
+use num::bigint::BigInt;
+use num::{Complex, Signed, ToPrimitive, Zero};
+use std::ops::{Add, Mul, Sub};
+use std::str::FromStr;
+
+/// 十进制指数的范围: 对齐尾数和转换成定点数时要乘以 10^指数, 指数没有上限时一个很短的字符串就能让计算停不下来
+///
+/// 微扰的偏移用 f64 表示, 视口的宽度小于 2^-1000 左右就下溢了, 所以这个范围已经足够
+const MAX_EXPONENT: i64 = 1000;
+
+/// 任意长度的十进制小数, 值为 mantissa * 10^exponent, exponent 在 ±MAX_EXPONENT 之间
+///
+/// 解析时不会经过 f64, 所以命令行上给出的每一位数字都会被保留下来
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decimal {
+    mantissa: BigInt,
+    exponent: i64,
+}
+
+impl FromStr for Decimal {
+    type Err = String;
+
+    /// 解析形如 "-1.25", "0.000123" 或 "1.5e-20" 的字符串
+    fn from_str(s: &str) -> Result<Decimal, String> {
+        let invalid = || format!("invalid decimal number {:?}", s);
+        let (number, exponent) = match s.find(['e', 'E']) {
+            None => (s, 0),
+            Some(index) => (&s[..index], s[index + 1..].parse::<i64>().map_err(|_| invalid())?),
+        };
+        let (integer, fraction) = number.split_once('.').unwrap_or((number, ""));
+        let digits = format!("{}{}", integer, fraction);
+        // 只允许一个可选的符号, 后面全部是数字, 并且至少有一位数字
+        let unsigned = digits.strip_prefix(['-', '+']).unwrap_or(&digits);
+        if unsigned.is_empty() || !unsigned.bytes().all(|b| b.is_ascii_digit()) || fraction.starts_with(['-', '+']) {
+            return Err(invalid());
+        }
+        let exponent = exponent
+            .checked_sub(fraction.len() as i64)
+            .filter(|exponent| (-MAX_EXPONENT..=MAX_EXPONENT).contains(exponent))
+            .ok_or_else(|| format!("{:?} is out of range: at most {} decimal places and exponents up to {} are supported", s, MAX_EXPONENT, MAX_EXPONENT))?;
+        Ok(Decimal { mantissa: digits.parse().map_err(|_| invalid())?, exponent })
+    }
+}
+
+impl Decimal {
+    /// 把两个数的尾数对齐到较小的指数, 返回对齐后的两个尾数
+    fn align(&self, other: &Decimal) -> (BigInt, BigInt) {
+        let exponent = self.exponent.min(other.exponent);
+        let aligned = |d: &Decimal| {
+            let gap = u32::try_from(d.exponent - exponent).expect("exponents are bounded by MAX_EXPONENT");
+            &d.mantissa * BigInt::from(10).pow(gap)
+        };
+        (aligned(self), aligned(other))
+    }
+
+    /// 精确比较大小
+    fn less_than(&self, other: &Decimal) -> bool {
+        let (a, b) = self.align(other);
+        a < b
+    }
+
+    /// |self - other| 以 2 为底的对数的近似值 (误差不超过 1), 两个数相等时返回负无穷
+    fn distance_log2(&self, other: &Decimal) -> f64 {
+        let (a, b) = self.align(other);
+        let difference = (a - b).abs();
+        if difference.is_zero() {
+            return f64::NEG_INFINITY;
+        }
+        difference.bits() as f64 + self.exponent.min(other.exponent) as f64 * std::f64::consts::LOG2_10
+    }
+}
+
+/// 把 "RE,IM" 形式的字符串解析成任意精度的复数, 格式与 parse_complex 相同
+pub fn parse_big_complex(s: &str) -> Result<Complex<Decimal>, RenderError> {
+    parse_pair(s, ',').map(|(re, im)| Complex { re, im })
+}
+
+/// 检查深度缩放的两个角点是否是左上角和右下角, 直接比较十进制数, 视口小于 f64 的精度时也能判断
+///
+/// 视口太小, 像素之间的偏移在 f64 中会下溢时同样返回错误
+pub fn check_corners(upper_left: &Complex<Decimal>, lower_right: &Complex<Decimal>) -> Result<(), RenderError> {
+    if !(upper_left.re.less_than(&lower_right.re) && lower_right.im.less_than(&upper_left.im)) {
+        return Err(RenderError::Viewport {
+            reason: "the corners must be the upper left and lower right corners, in that order".to_string(),
+        });
+    }
+    let size = upper_left.re.distance_log2(&lower_right.re).min(upper_left.im.distance_log2(&lower_right.im));
+    if size < MIN_SIZE_LOG2 {
+        return Err(RenderError::Viewport {
+            reason: format!("the viewport is smaller than 2^{}, too small even for --deep", MIN_SIZE_LOG2),
+        });
+    }
+    Ok(())
+}
+
+/// 视口宽和高以 2 为底的对数的下限, 再小下去像素的间距在 f64 中就是非正规数甚至 0 了
+const MIN_SIZE_LOG2: f64 = -960.0;
+
+/// 二进制定点数, 值为 value / 2^bits
+///
+/// 参与运算的两个数必须具有相同的精度 bits
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fixed {
+    value: BigInt,
+    bits: usize,
+}
+
+impl Fixed {
+    pub fn from_decimal(decimal: &Decimal, bits: usize) -> Fixed {
+        let scaled = decimal.mantissa.clone() << bits;
+        let power = u32::try_from(decimal.exponent.unsigned_abs()).expect("exponents are bounded by MAX_EXPONENT");
+        let value = if decimal.exponent >= 0 {
+            scaled * BigInt::from(10).pow(power)
+        } else {
+            scaled / BigInt::from(10).pow(power)
+        };
+        Fixed { value, bits }
+    }
+
+    /// 转换成最接近的 f64, 极小的数可以正常转换, 不会因为先除以 2^bits 而下溢
+    pub fn to_f64(&self) -> f64 {
+        if self.value.is_zero() {
+            return 0.0;
+        }
+        // 只保留最高的 64 位, 剩下的部分用 2 的幂补上
+        let shift = self.value.bits() as i64 - 64;
+        let (top, exponent) = if shift > 0 {
+            (&self.value >> shift as usize, shift - self.bits as i64)
+        } else {
+            (self.value.clone(), -(self.bits as i64))
+        };
+        let top = top.to_f64().unwrap();
+        // 2^exponent 可能超出 f64 的指数范围, 分两步缩放
+        let half = exponent / 2;
+        top * 2f64.powi(half as i32) * 2f64.powi((exponent - half) as i32)
+    }
+
+    fn half(&self) -> Fixed {
+        Fixed { value: &self.value >> 1usize, bits: self.bits }
+    }
+
+    fn abs(&self) -> Fixed {
+        Fixed { value: self.value.abs(), bits: self.bits }
+    }
+}
+
+impl Add for &Fixed {
+    type Output = Fixed;
+    fn add(self, rhs: &Fixed) -> Fixed {
+        assert_eq!(self.bits, rhs.bits);
+        Fixed { value: &self.value + &rhs.value, bits: self.bits }
+    }
+}
+
+impl Sub for &Fixed {
+    type Output = Fixed;
+    fn sub(self, rhs: &Fixed) -> Fixed {
+        assert_eq!(self.bits, rhs.bits);
+        Fixed { value: &self.value - &rhs.value, bits: self.bits }
+    }
+}
+
+impl Mul for &Fixed {
+    type Output = Fixed;
+    fn mul(self, rhs: &Fixed) -> Fixed {
+        assert_eq!(self.bits, rhs.bits);
+        Fixed { value: (&self.value * &rhs.value) >> self.bits, bits: self.bits }
+    }
+}
+
+/// 深度缩放的视口: 高精度的中心点, 以及用 f64 表示的相对偏移
+pub struct DeepView {
+    /// 视口中心的参考轨道 Z_0, Z_1, ..., 最后一个元素可能已经逃逸
+    orbit: Vec<Complex<f64>>,
+    /// 左上角像素相对于中心的偏移
+    offset: Complex<f64>,
+    /// 相邻像素在实轴和虚轴方向上的间距
+    step: (f64, f64),
+}
+
+impl DeepView {
+    /// 根据任意精度的左上角和右下角建立视口, 并用高精度算出中心的参考轨道
+    pub fn new(
+        bounds: (usize, usize),
+        upper_left: &Complex<Decimal>,
+        lower_right: &Complex<Decimal>,
+        options: &RenderOptions,
+    ) -> DeepView {
+        // 定点数只需要比像素的间距再精细 64 位, 与角点写了多少位数字无关
+        let spacing = (upper_left.re.distance_log2(&lower_right.re) - (bounds.0 as f64).log2())
+            .min(upper_left.im.distance_log2(&lower_right.im) - (bounds.1 as f64).log2());
+        let bits = (-spacing).max(0.0).ceil() as usize + 64;
+        let fixed = |d: &Decimal| Fixed::from_decimal(d, bits);
+        let (left, top) = (fixed(&upper_left.re), fixed(&upper_left.im));
+        let (right, bottom) = (fixed(&lower_right.re), fixed(&lower_right.im));
+
+        let center = Complex { re: (&left + &right).half(), im: (&top + &bottom).half() };
+        let offset = Complex { re: (&left - &center.re).to_f64(), im: (&top - &center.im).to_f64() };
+        let step = (
+            (&right - &left).abs().to_f64() / bounds.0 as f64,
+            (&top - &bottom).abs().to_f64() / bounds.1 as f64,
+        );
+        let orbit = reference_orbit(&center, options.limit, options.escape_radius);
+        DeepView { orbit, offset, step }
+    }
+
+    /// 像素 (column, row) 相对于视口中心的偏移 δc
+    fn delta(&self, column: usize, row: usize) -> Complex<f64> {
+        Complex {
+            re: self.offset.re + column as f64 * self.step.0,
+            im: self.offset.im - row as f64 * self.step.1,
+        }
+    }
+
+    /// 用微扰公式计算像素 (column, row) 的逃逸值, 含义与 render 中的逃逸值相同
+    ///
+    /// 当 |z_n| 比 |δz_n| 还小, 或者参考轨道已经用完时, 把当前的 z_n 当作新的 δz, 从参考轨道的开头重新开始 (rebasing)
+    /// 这样即使参考点本身很快就逃逸了, 其他像素也能继续迭代, 并且避免了 δz 相对于 z 过大时的精度损失 (glitch)
+    fn escape_value(&self, column: usize, row: usize, options: &RenderOptions) -> Option<f64> {
+        let bailout = options.escape_radius * options.escape_radius;
+        let dc = self.delta(column, row);
+        let mut dz = Complex { re: 0.0, im: 0.0 };
+        let mut m = 0;
+        for i in 0..options.limit {
+            let z = self.orbit[m] + dz;
+            if z.norm_sqr() > bailout {
+                return Some(if options.smooth {
+                    smooth_escape(i, z, bailout, 2.0, options.limit)
+                } else {
+                    i as f64
+                });
+            }
+            if z.norm_sqr() < dz.norm_sqr() || m + 1 == self.orbit.len() {
+                dz = z;
+                m = 0;
+            }
+            dz = (self.orbit[m] * 2.0 + dz) * dz + dc;
+            m += 1;
+        }
+
+        None
+    }
+}
+
+/// 用定点数计算 c 的轨道 Z_0 = 0, Z_{n+1} = Z_n^2 + c, 直到逃逸 (包含第一个逃逸的值) 或者达到 limit 次迭代
+///
+/// 返回的轨道至少包含 Z_0 和 Z_1 两个元素
+fn reference_orbit(c: &Complex<Fixed>, limit: usize, escape_radius: f64) -> Vec<Complex<f64>> {
+    let bailout = escape_radius * escape_radius;
+    let zero = Fixed { value: BigInt::zero(), bits: c.re.bits };
+    let (mut re, mut im) = (zero.clone(), zero);
+    let mut orbit = vec![Complex { re: 0.0, im: 0.0 }];
+    for _ in 0..limit {
+        let re_im = &re * &im;
+        re = &(&(&re * &re) - &(&im * &im)) + &c.re;
+        im = &(&re_im + &re_im) + &c.im;
+        let z = Complex { re: re.to_f64(), im: im.to_f64() };
+        orbit.push(z);
+        if z.norm_sqr() > bailout {
+            break;
+        }
+    }
+    orbit
+}
+
+/// 用深度缩放的方式渲染整幅图像, 只支持曼德博集的公式
+///
+/// 参数的含义与 parallel::Backend::render 相同, 只是两个角点是任意精度的
+pub fn render_deep(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    upper_left: &Complex<Decimal>,
+    lower_right: &Complex<Decimal>,
+    options: &RenderOptions,
+    threads: usize,
+) {
+    let channels = options.palette.channels();
+    assert!(pixels.len() == bounds.0 * bounds.1 * channels);
+
+    let view = DeepView::new(bounds, upper_left, lower_right, options);
+    render_rows_parallel(pixels, bounds.0 * channels, threads, |row, band| {
+        if options.cancelled() {
+            return;
+        }
+        for column in 0..bounds.0 {
+            let t = view.escape_value(column, row, options).map(|value| value / options.limit as f64);
+            options.palette.paint(&mut band[column * channels..(column + 1) * channels], t);
+        }
+        options.advance(1);
+    });
+}
+
+#[test]
+fn test_parse_decimal() {
+    let d: Decimal = "-1.2500".parse().unwrap();
+    assert_eq!(d, Decimal { mantissa: BigInt::from(-12500), exponent: -4 });
+    let d: Decimal = "3e2".parse().unwrap();
+    assert_eq!(d, Decimal { mantissa: BigInt::from(3), exponent: 2 });
+    let d: Decimal = "1.5E-20".parse().unwrap();
+    assert_eq!(d, Decimal { mantissa: BigInt::from(15), exponent: -21 });
+    // 超出 f64 精度的数字也会被完整地保留
+    let d: Decimal = "0.123456789012345678901234567890".parse().unwrap();
+    assert_eq!(d.mantissa.to_string(), "123456789012345678901234567890");
+    assert_eq!(d.exponent, -30);
+
+    for bad in ["", "-", ".", "1.2.3", "1,5", "abc", "1e", "1.-5"] {
+        assert!(bad.parse::<Decimal>().is_err(), "{:?}", bad);
+    }
+    // 指数超出范围时在解析阶段就报错, 而不是在计算 10 的幂时卡住
+    for bad in ["1e-999999", "1e1001", "1e-9223372036854775808", &format!("0.{}1", "0".repeat(1000))] {
+        assert!(bad.parse::<Decimal>().unwrap_err().contains("out of range"), "{:?}", bad);
+    }
+    assert!("1e1000".parse::<Decimal>().is_ok());
+}
+
+#[test]
+fn test_parse_big_complex() {
+    let c = parse_big_complex("-0.75000000000000000000001,0.1").unwrap();
+    assert_eq!(c.re.mantissa.to_string(), "-75000000000000000000001");
+    assert_eq!(c.im, "0.1".parse().unwrap());
+    assert!(parse_big_complex(",0.1").is_err());
+
+    // 两个角点只在第 30 位小数上不同, 转换成 f64 之后无法区分
+    let upper_left = parse_big_complex("-0.75,0.100000000000000000000000000001").unwrap();
+    let lower_right = parse_big_complex("-0.749999999999999999999999999999,0.1").unwrap();
+    assert!(check_corners(&upper_left, &lower_right).is_ok());
+    assert!(check_corners(&lower_right, &upper_left).is_err());
+    assert!(check_corners(&upper_left, &upper_left).is_err());
+
+    // 指数相差很大的两个数也能正确比较
+    let (huge, tiny): (Decimal, Decimal) = ("1e900".parse().unwrap(), "-1e-900".parse().unwrap());
+    assert!(tiny.less_than(&huge) && !huge.less_than(&tiny));
+    // 视口太小时像素的间距会在 f64 中下溢
+    let upper_left = parse_big_complex("0,1e-300").unwrap();
+    let lower_right = parse_big_complex("1e-300,0").unwrap();
+    assert!(matches!(check_corners(&upper_left, &lower_right), Err(RenderError::Viewport { .. })));
+}
+
+#[test]
+fn test_fixed() {
+    let bits = 256;
+    let a = Fixed::from_decimal(&"1.5".parse().unwrap(), bits);
+    let b = Fixed::from_decimal(&"-0.25".parse().unwrap(), bits);
+    assert_eq!((&a * &b).to_f64(), -0.375);
+    assert_eq!((&a + &b).to_f64(), 1.25);
+    assert_eq!((&a - &b).to_f64(), 1.75);
+    // 两个只在第 30 位小数上不同的数, 它们的差仍然可以精确地求出来
+    let x = Fixed::from_decimal(&"0.100000000000000000000000000003".parse().unwrap(), bits);
+    let y = Fixed::from_decimal(&"0.1".parse().unwrap(), bits);
+    let diff = (&x - &y).to_f64();
+    assert!((diff - 3e-30).abs() < 1e-40, "{}", diff);
+}
+
+#[test]
+fn test_render_deep_matches_f64() {
+    // 在 f64 足够精确的缩放级别上, 微扰的结果应当与直接迭代几乎完全一致
+    use crate::render;
+    let bounds = (48, 36);
+    let (ul, lr) = ("-0.8,0.2", "-0.7,0.125");
+    let options = RenderOptions { limit: 500, ..RenderOptions::default() };
+    let mut expected = vec![0; bounds.0 * bounds.1];
+    let (upper_left, lower_right) = (Complex { re: -0.8, im: 0.2 }, Complex { re: -0.7, im: 0.125 });
+    render(&mut expected, bounds, upper_left, lower_right, &options);
+
+    let mut pixels = vec![0; bounds.0 * bounds.1];
+    let (ul, lr) = (parse_big_complex(ul).unwrap(), parse_big_complex(lr).unwrap());
+    render_deep(&mut pixels, bounds, &ul, &lr, &options, 2);
+    let same = pixels.iter().zip(&expected).filter(|(a, b)| a == b).count();
+    assert!(same * 100 >= pixels.len() * 99, "{} of {} pixels match", same, pixels.len());
+}
+
+#[test]
+fn test_render_deep_beyond_f64() {
+    // 视口是 c = i (位于集合边界上的 Misiurewicz 点) 附近宽度为 2e-30 的正方形
+    // f64 无法区分其中的像素, 深度缩放仍然能画出不同的逃逸值
+    let bounds = (16, 16);
+    let ul = parse_big_complex("-0.000000000000000000000000000001,1.000000000000000000000000000001").unwrap();
+    let lr = parse_big_complex("0.000000000000000000000000000001,0.999999999999999999999999999999").unwrap();
+    let options = RenderOptions { limit: 2000, ..RenderOptions::default() };
+    let mut pixels = vec![0; bounds.0 * bounds.1];
+    render_deep(&mut pixels, bounds, &ul, &lr, &options, 2);
+    let mut distinct = pixels.clone();
+    distinct.sort();
+    distinct.dedup();
+    assert!(distinct.len() > 2, "{:?}", distinct);
+}
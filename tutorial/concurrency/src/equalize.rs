@@ -0,0 +1,156 @@
+//! 直方图均衡化着色
+//!
+//! 逃逸次数的分布极不均匀: 大部分像素在最初几次迭代就逃逸了, 直接按 count / limit 着色时只用到了很窄的一段灰度
+//! 这里分两遍渲染: 第一遍计算所有像素的逃逸值并统计直方图, 第二遍按累积分布函数着色,
+//! 这样每一段颜色覆盖的像素数大致相同, 无论缩放到哪里对比度都一致
+
+use crate::{render_escapes, RenderOptions};
+use num::Complex;
+
+/// 直方图最多的格子数; 迭代上限更大时一个格子覆盖好几次迭代, 直方图的内存不随迭代上限增长
+pub const MAX_BINS: usize = 1 << 16;
+
+/// 逃逸值的直方图, bins[i] 是逃逸值落在 [i * width, (i + 1) * width) 内的像素数, 没有逃逸的像素不计入
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    bins: Vec<u64>,
+    /// 每个格子覆盖的逃逸值的宽度, 迭代上限不超过 MAX_BINS 时为 1
+    width: f64,
+}
+
+impl Histogram {
+    /// 迭代上限为 limit 时逃逸值在 [0, limit] 之间, 需要 limit + 1 个宽度为 1 的格子, 但最多 MAX_BINS 个
+    pub fn new(limit: usize) -> Histogram {
+        let bins = limit.saturating_add(1).min(MAX_BINS);
+        Histogram { bins: vec![0; bins], width: (limit as f64 + 1.0) / bins as f64 }
+    }
+
+    pub fn add(&mut self, escape: f64) {
+        let bin = ((escape.max(0.0) / self.width) as usize).min(self.bins.len() - 1);
+        self.bins[bin] += 1;
+    }
+
+    /// other 必须是同一个迭代上限的直方图
+    pub fn merge(&mut self, other: &Histogram) {
+        assert!(self.bins.len() == other.bins.len());
+        for (bin, &count) in self.bins.iter_mut().zip(&other.bins) {
+            *bin += count;
+        }
+    }
+
+    /// 用 threads 个线程统计 escapes 的直方图: 每个线程统计自己的一段, 最后把各自的直方图合并起来
+    pub fn collect(escapes: &[Option<f64>], limit: usize, threads: usize) -> Histogram {
+        let band_len = escapes.len().div_ceil(threads).max(1);
+        crossbeam::scope(|spawner| {
+            let handles: Vec<_> = escapes
+                .chunks(band_len)
+                .map(|band| {
+                    spawner.spawn(move |_| {
+                        let mut histogram = Histogram::new(limit);
+                        band.iter().flatten().for_each(|&escape| histogram.add(escape));
+                        histogram
+                    })
+                })
+                .collect();
+            let mut total = Histogram::new(limit);
+            for handle in handles {
+                total.merge(&handle.join().unwrap());
+            }
+            total
+        })
+        .unwrap()
+    }
+
+    /// 累积分布函数: cumulative[i] 是逃逸值小于 i * width 的像素所占的比例
+    pub fn cdf(&self) -> Cdf {
+        let total = self.bins.iter().sum::<u64>().max(1) as f64;
+        let mut cumulative = Vec::with_capacity(self.bins.len() + 1);
+        let mut sum = 0;
+        cumulative.push(0.0);
+        for &count in &self.bins {
+            sum += count;
+            cumulative.push(sum as f64 / total);
+        }
+        Cdf { cumulative, width: self.width }
+    }
+}
+
+pub struct Cdf {
+    cumulative: Vec<f64>,
+    width: f64,
+}
+
+impl Cdf {
+    /// 把逃逸值映射到 [0, 1], 在格子的两端之间做线性插值, 所以平滑模式下的连续逃逸值仍然是连续的
+    pub fn map(&self, escape: f64) -> f64 {
+        let last = self.cumulative.len() - 2;
+        let position = escape.max(0.0) / self.width;
+        let bin = (position as usize).min(last);
+        let fraction = (position - bin as f64).clamp(0.0, 1.0);
+        self.cumulative[bin] + (self.cumulative[bin + 1] - self.cumulative[bin]) * fraction
+    }
+}
+
+#[test]
+fn test_histogram_cdf() {
+    let escapes = [Some(0.0), Some(1.0), Some(1.5), None, Some(9.0), Some(10.0), Some(1.2), None];
+    let histogram = Histogram::collect(&escapes, 10, 3);
+    let mut expected = Histogram::new(10);
+    escapes.iter().flatten().for_each(|&escape| expected.add(escape));
+    assert_eq!(histogram, expected);
+    assert_eq!(histogram.bins, [1, 3, 0, 0, 0, 0, 0, 0, 0, 1, 1]);
+
+    let cdf = histogram.cdf();
+    let expected = [(0.0, 0.0), (1.0, 1.0 / 6.0), (1.5, 2.5 / 6.0), (5.0, 4.0 / 6.0), (10.0, 5.0 / 6.0), (10.5, 5.5 / 6.0)];
+    for (escape, expected) in expected {
+        assert!((cdf.map(escape) - expected).abs() < 1e-12, "{}: {}", escape, cdf.map(escape));
+    }
+
+    // 迭代上限很大时格子数不变, 每个格子覆盖多次迭代
+    let limit = 3_000_000_000;
+    let escapes = [Some(1.0), Some(2.0), Some(1e9), Some(3e9)];
+    let histogram = Histogram::collect(&escapes, limit, 2);
+    assert_eq!(histogram.bins.len(), MAX_BINS);
+    assert_eq!((histogram.bins[0], histogram.bins[MAX_BINS - 1]), (2, 1));
+    let cdf = histogram.cdf();
+    assert_eq!(cdf.map(0.0), 0.0);
+    assert!((cdf.map(2e9) - 0.75).abs() < 1e-12);
+    assert!((cdf.map(limit as f64 + 1.0) - 1.0).abs() < 1e-12);
+}
+
+/// 两遍的均衡化渲染, 参数与 render 相同, threads 是两遍所用的线程数
+///
+/// 第一遍只计算每个像素左上角的逃逸值, 所以不支持超采样
+pub fn render_equalized(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    options: &RenderOptions,
+    threads: usize,
+) {
+    assert!(options.samples == 1 && !options.jitter, "histogram equalization does not support supersampling");
+    let map = render_escapes(bounds, upper_left, lower_right, options, threads);
+    let cdf = Histogram::collect(&map.escapes, options.limit, threads).cdf();
+    pixels.copy_from_slice(&map.paint_by(&options.palette, |escape| cdf.map(escape)));
+}
+
+#[test]
+fn test_render_equalized() {
+    // 均衡化之后, 逃逸的像素大致均匀地分布在整个灰度范围内
+    let bounds = (120, 90);
+    let upper_left = Complex { re: -2.0, im: 1.2 };
+    let lower_right = Complex { re: 1.0, im: -1.2 };
+    let options = RenderOptions { equalize: true, ..RenderOptions::default() };
+    let mut pixels = vec![0; bounds.0 * bounds.1];
+    render_equalized(&mut pixels, bounds, upper_left, lower_right, &options, 4);
+    let mut single = vec![0; pixels.len()];
+    render_equalized(&mut single, bounds, upper_left, lower_right, &options, 1);
+    assert_eq!(pixels, single);
+
+    // 不均衡时几乎所有逃逸的像素都挤在最亮的一端
+    let mut plain = vec![0; pixels.len()];
+    crate::render(&mut plain, bounds, upper_left, lower_right, &RenderOptions::default());
+    let below_200 = |pixels: &[u8]| pixels.iter().filter(|&&p| p > 0 && p < 200).count();
+    assert!(below_200(&plain) * 10 < below_200(&pixels), "{} vs {}", below_200(&plain), below_200(&pixels));
+}
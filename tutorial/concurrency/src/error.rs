@@ -0,0 +1,101 @@
+//! 渲染流程中的错误
+//!
+//! 参数解析, 渲染参数, 图像尺寸, 视口, 渲染线程和文件读写出错时都返回 RenderError, 由 main 统一输出错误信息并以不同的退出码退出
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum RenderError {
+    /// input 无法解析, position 是出错部分在 input 中的字节偏移
+    Parse { input: String, position: usize, reason: String },
+    /// 图像的尺寸不能用来渲染, 例如宽或高为 0
+    Dimension { bounds: (usize, usize), reason: String },
+    /// 渲染参数 option 的值 value 不能用来渲染, option 是 RenderOptions 或 Renderer 的字段名
+    Options { option: &'static str, value: String, reason: String },
+    /// 两个角点围成的视口不能用来渲染, 例如角点颠倒或者超出了 f64 的精度
+    Viewport { reason: String },
+    /// 读写 path 时出错
+    Io { path: String, source: io::Error },
+    /// 无法启动 threads 个渲染线程, 例如建立 rayon 线程池失败
+    ThreadPool { threads: usize, source: rayon::ThreadPoolBuildError },
+}
+
+impl RenderError {
+    pub fn parse(input: &str, position: usize, reason: impl Into<String>) -> RenderError {
+        RenderError::Parse { input: input.to_string(), position, reason: reason.into() }
+    }
+
+    /// 用在 map_err 中, 给 io::Error 加上出错的文件名
+    pub fn io(path: impl fmt::Display) -> impl FnOnce(io::Error) -> RenderError {
+        let path = path.to_string();
+        move |source| RenderError::Io { path, source }
+    }
+
+    /// 进程的退出码: 参数格式错误与 clap 报告的用法错误一样是 2, 其余各不相同
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RenderError::Parse { .. } | RenderError::Options { .. } => 2,
+            RenderError::Dimension { .. } => 3,
+            RenderError::Viewport { .. } => 4,
+            RenderError::Io { .. } => 5,
+            RenderError::ThreadPool { .. } => 6,
+        }
+    }
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenderError::Parse { input, position, reason } => {
+                // 在原文下面用 ^ 标出出错的位置
+                let column = input[..*position].chars().count();
+                write!(f, "{} at column {}\n    {}\n    {}^", reason, column + 1, input, " ".repeat(column))
+            }
+            RenderError::Options { option, value, reason } => write!(f, "invalid value '{}' for {}: {}", value, option, reason),
+            RenderError::Dimension { bounds, reason } => write!(f, "invalid image size {}x{}: {}", bounds.0, bounds.1, reason),
+            RenderError::Viewport { reason } => write!(f, "invalid viewport: {}", reason),
+            RenderError::Io { path, source } => write!(f, "{}: {}", path, source),
+            RenderError::ThreadPool { threads, source } => write!(f, "cannot start {} rendering threads: {}", threads, source),
+        }
+    }
+}
+
+impl Error for RenderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RenderError::Io { source, .. } => Some(source),
+            RenderError::ThreadPool { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_render_error() {
+    let error = RenderError::parse("1.5;2", 5, "expected ','");
+    assert_eq!(error.to_string(), "expected ',' at column 6\n    1.5;2\n         ^");
+    assert_eq!(error.exit_code(), 2);
+
+    let error = RenderError::io("a.png")(io::Error::new(io::ErrorKind::NotFound, "not found"));
+    assert_eq!(error.to_string(), "a.png: not found");
+    assert!(error.source().is_some());
+
+    // 全局线程池只能建立一次, 第二次一定失败, 用它得到一个 ThreadPoolBuildError
+    let _ = rayon::ThreadPoolBuilder::new().build_global();
+    let source = rayon::ThreadPoolBuilder::new().build_global().unwrap_err();
+    let pool = RenderError::ThreadPool { threads: 4, source };
+    assert!(pool.to_string().starts_with("cannot start 4 rendering threads: "), "{}", pool);
+    let codes: Vec<i32> = [
+        error,
+        RenderError::Dimension { bounds: (0, 1), reason: String::new() },
+        RenderError::Viewport { reason: String::new() },
+        RenderError::Options { option: "limit", value: "0".to_string(), reason: String::new() },
+        pool,
+    ]
+    .iter()
+    .map(RenderError::exit_code)
+    .collect();
+    assert_eq!(codes, [5, 3, 4, 2, 6]);
+}
@@ -0,0 +1,111 @@
+use num::Complex;
+use std::sync::Arc;
+
+/// 逃逸时间分形的迭代公式
+///
+/// 渲染器只关心怎样从 z 得到下一个 z, 新的公式只需要实现这个 trait, 不需要再复制一份 render
+/// 渲染时多个线程会共享同一个公式, 所以要求实现 Sync; 瓦片服务器还要把公式移动到工作线程中, 所以也要求 Send
+pub trait Fractal: Send + Sync {
+    /// 迭代一步, 返回下一个 z
+    fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64>;
+
+    /// 公式中 z 的次数, 平滑着色时用它做 log-log 归一化
+    fn degree(&self) -> f64 {
+        2.0
+    }
+
+    /// 公式是否就是 z^2 + c, 是的话渲染器可以改用 simd 模块中的向量化内核
+    fn is_quadratic(&self) -> bool {
+        false
+    }
+}
+
+/// 曼德博集: z = z^2 + c
+pub struct Mandelbrot;
+
+impl Fractal for Mandelbrot {
+    fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        z * z + c
+    }
+
+    fn is_quadratic(&self) -> bool {
+        true
+    }
+}
+
+/// 燃烧船分形: z = (|Re z| + i|Im z|)^2 + c
+pub struct BurningShip;
+
+impl Fractal for BurningShip {
+    fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        let z = Complex { re: z.re.abs(), im: z.im.abs() };
+        z * z + c
+    }
+}
+
+/// 三角分形 (Tricorn): z = conj(z)^2 + c
+pub struct Tricorn;
+
+impl Fractal for Tricorn {
+    fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        let z = z.conj();
+        z * z + c
+    }
+}
+
+/// 广义曼德博集 (Multibrot): z = z^d + c
+pub struct Multibrot {
+    pub degree: u32,
+}
+
+impl Fractal for Multibrot {
+    fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        z.powu(self.degree) + c
+    }
+
+    fn degree(&self) -> f64 {
+        self.degree as f64
+    }
+}
+
+/// 根据名字选择公式: mandelbrot, burning-ship, tricorn 或者 multibrot:D (D 是不小于 2 的整数次数)
+///
+/// 名字无法识别时返回 None
+pub fn from_name(name: &str) -> Option<Arc<dyn Fractal>> {
+    match name {
+        "mandelbrot" => Some(Arc::new(Mandelbrot)),
+        "burning-ship" => Some(Arc::new(BurningShip)),
+        "tricorn" => Some(Arc::new(Tricorn)),
+        _ => {
+            let degree: u32 = name.strip_prefix("multibrot:")?.parse().ok()?;
+            if degree < 2 {
+                return None;
+            }
+            Some(Arc::new(Multibrot { degree }))
+        }
+    }
+}
+
+#[test]
+fn test_step() {
+    let z = Complex { re: -1.0, im: 2.0 };
+    let c = Complex { re: 0.5, im: 0.25 };
+    assert_eq!(Mandelbrot.step(z, c), Complex { re: -2.5, im: -3.75 });
+    assert_eq!(BurningShip.step(z, c), Complex { re: -2.5, im: 4.25 });
+    assert_eq!(Tricorn.step(z, c), Complex { re: -2.5, im: 4.25 });
+    assert_eq!(Tricorn.step(Complex { re: 1.0, im: 2.0 }, c), Complex { re: -2.5, im: -3.75 });
+    // 次数为 2 的 Multibrot 就是曼德博集
+    assert_eq!(Multibrot { degree: 2 }.step(z, c), Mandelbrot.step(z, c));
+    assert_eq!(Multibrot { degree: 3 }.step(z, c), Complex { re: 11.5, im: -1.75 });
+}
+
+#[test]
+fn test_from_name() {
+    assert_eq!(from_name("mandelbrot").unwrap().degree(), 2.0);
+    assert_eq!(from_name("multibrot:5").unwrap().degree(), 5.0);
+    assert!(from_name("burning-ship").is_some());
+    assert!(from_name("tricorn").is_some());
+    assert!(from_name("multibrot:1").is_none());
+    assert!(from_name("multibrot:x").is_none());
+    assert!(from_name("julia").is_none());
+}
@@ -0,0 +1,96 @@
+//! 加速集合内部的点
+//!
+//! 集合内部的点永远不会逃逸, 普通的循环必须跑满 limit 次迭代才能下结论, 它们往往占据了大部分渲染时间
+//! 这里的两种手段都只会提前返回本来就会得到的 None, 因此结果与普通的循环逐位一致
+
+use crate::fractal::Fractal;
+use num::Complex;
+
+/// c 是否位于曼德博集的主心形 (main cardioid) 或者周期为 2 的圆盘 (period-2 bulb) 之内
+///
+/// 这两个区域有封闭形式的边界, 区域内的 c 从 z = 0 开始迭代都会收敛到吸引周期轨道, 不需要迭代就可以判定
+pub fn in_cardioid_or_bulb(c: Complex<f64>) -> bool {
+    let y2 = c.im * c.im;
+    // 主心形: q (q + (x - 1/4)) < y^2 / 4, 其中 q = (x - 1/4)^2 + y^2
+    let q = (c.re - 0.25) * (c.re - 0.25) + y2;
+    if q * (q + (c.re - 0.25)) < 0.25 * y2 {
+        return true;
+    }
+    // 周期 2 圆盘: 以 -1 为圆心, 半径为 1/4
+    (c.re + 1.0) * (c.re + 1.0) + y2 < 0.0625
+}
+
+#[test]
+fn test_in_cardioid_or_bulb() {
+    for &(re, im) in &[(0.0, 0.0), (-0.5, 0.5), (0.2, 0.0), (-1.0, 0.0), (-1.2, 0.1)] {
+        assert!(in_cardioid_or_bulb(Complex { re, im }), "{} {}", re, im);
+    }
+    // 集合外的点, 以及集合内但不在这两个区域中的点 (例如周期 3 的圆盘)
+    for &(re, im) in &[(0.3, 0.0), (-2.0, 0.1), (0.0, 1.0), (-0.12, 0.75), (-1.3, 0.0)] {
+        assert!(!in_cardioid_or_bulb(Complex { re, im }), "{} {}", re, im);
+    }
+}
+
+/// 带有周期检测的 escape_time, 逃逸时返回 Some((i, z)), 其中 z 是逃逸时的值
+///
+/// 采用 Brent 的做法: 每隔 1, 2, 4, 8, ... 步保存一次当前的 z, 之后只要迭代出与它完全相同 (逐位相等) 的 z,
+/// 就说明浮点数轨道进入了循环, 永远不会逃逸, 可以立刻返回 None
+/// 比较时不使用容差, 所以只会在普通循环也必然返回 None 的时候提前结束
+pub fn escape_time_periodic<F: Fractal + ?Sized>(
+    fractal: &F,
+    mut z: Complex<f64>,
+    c: Complex<f64>,
+    limit: usize,
+    escape_radius: f64,
+) -> Option<(usize, Complex<f64>)> {
+    let bailout = escape_radius * escape_radius;
+    let mut saved = z;
+    let (mut steps, mut period): (usize, usize) = (0, 1);
+    for i in 0..limit {
+        if z.norm_sqr() > bailout {
+            return Some((i, z));
+        }
+        z = fractal.step(z, c);
+        if z == saved {
+            return None;
+        }
+        steps += 1;
+        if steps == period {
+            saved = z;
+            steps = 0;
+            period *= 2;
+        }
+    }
+
+    None
+}
+
+#[test]
+fn test_escape_time_periodic() {
+    use crate::escape_time;
+    use crate::fractal::{BurningShip, Mandelbrot};
+
+    let zero = Complex { re: 0.0, im: 0.0 };
+    // c = 0 和 c = -1 的轨道是 0 -> 0 和 0 -> -1 -> 0, 很快就能检测到循环
+    assert_eq!(escape_time_periodic(&Mandelbrot, zero, zero, 1_000_000, 2.0), None);
+    assert_eq!(escape_time_periodic(&Mandelbrot, zero, Complex { re: -1.0, im: 0.0 }, 1_000_000, 2.0), None);
+    assert_eq!(
+        escape_time_periodic(&Mandelbrot, zero, Complex { re: 1.0, im: 0.0 }, 255, 2.0),
+        Some((3, Complex { re: 5.0, im: 0.0 }))
+    );
+
+    // 在网格上与普通的循环逐点比较
+    for row in 0..40 {
+        for column in 0..60 {
+            let c = Complex { re: -2.1 + column as f64 * 0.045, im: 1.2 - row as f64 * 0.06 };
+            assert_eq!(
+                escape_time_periodic(&Mandelbrot, zero, c, 1000, 2.0).map(|(i, _)| i),
+                escape_time(&Mandelbrot, zero, c, 1000, 2.0)
+            );
+            assert_eq!(
+                escape_time_periodic(&BurningShip, zero, c, 300, 2.0).map(|(i, _)| i),
+                escape_time(&BurningShip, zero, c, 300, 2.0)
+            );
+        }
+    }
+}
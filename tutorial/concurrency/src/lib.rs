@@ -0,0 +1,817 @@
+//! 并发渲染曼德博集 (以及茹利亚集等其他分形) 的库
+//!
+//! 嵌入时通常只需要 Renderer 和 Viewport: 用 RenderOptions 设置公式, 调色板和迭代次数, 再渲染任意的视口
+//! escape_time, pixel_to_point, render 等底层函数也是公开的; 命令行程序见 src/bin/mandelbrot
+//! 默认的 cli feature 会带上命令行程序和瓦片服务器的依赖, 只嵌入渲染库时可以用 default-features = false 去掉它们
+//!
+//! ```no_run
+//! use concurrency::{Renderer, Viewport};
+//! use num::Complex;
+//!
+//! let renderer = Renderer::default();
+//! let viewport = Viewport { center: Complex { re: -0.75, im: 0.0 }, zoom: 1.0, rotation: 0.0 };
+//! renderer.render_to_file("mandel.png", (800, 600), &viewport).unwrap();
+//! ```
+
+use num::Complex;
+use std::str::FromStr;
+use image::ColorType;
+use std::fs::File;
+use std::sync::Arc;
+
+pub mod animation;
+pub mod buddhabrot;
+pub mod deep;
+mod equalize;
+pub mod error;
+pub mod fractal;
+mod interior;
+pub mod metadata;
+pub mod output;
+pub mod palette;
+pub mod parallel;
+pub mod progress;
+mod renderer;
+#[cfg(feature = "server")]
+pub mod server;
+mod simd;
+mod supersample;
+pub mod tiles;
+pub mod viewport;
+pub use error::RenderError;
+pub use renderer::Renderer;
+pub use viewport::Viewport;
+use fractal::{Fractal, Mandelbrot};
+use palette::Palette;
+use viewport::Rotation;
+
+/// 从 z 开始用 fractal 的公式迭代 (曼德博集为 z = z * z + c), 使用最多 limit 次迭代来判定它是否逃逸
+///
+/// 曼德博集从 z = 0 开始, c 是待测的点; 茹利亚集 (Julia set) 则以待测的点作为起始的 z, c 是固定的参数
+/// 如果迭代逃逸, 则返回 Some(i), 其中 i 是 z 离开以原点为中心的半径为 escape_radius 的圆时需要的迭代次数
+/// 如果可能是集合成员之一(即达到了迭代次数限制后仍然无法证明不是成员), 则返回 None
+/// escape_radius 不能小于 2, 否则集合外的点也可能被误判为成员
+pub fn escape_time<F: Fractal + ?Sized>(
+    fractal: &F,
+    mut z: Complex<f64>,
+    c: Complex<f64>,
+    limit: usize,
+    escape_radius: f64,
+) -> Option<usize> {
+    let bailout = escape_radius * escape_radius;
+    for i in 0..limit {
+        if z.norm_sqr() > bailout {
+            return Some(i);
+        }
+        z = fractal.step(z, c);
+    }
+
+    None
+}
+
+#[test]
+fn test_escape_time() {
+    let zero = Complex { re: 0.0, im: 0.0 };
+    assert_eq!(escape_time(&Mandelbrot, zero, Complex { re: -2.5, im: 0.0 }, 255, 2.0), Some(1));
+    assert_eq!(escape_time(&Mandelbrot, zero, Complex { re: 1.0, im: 0.0 }, 255, 2.0), Some(3));
+    // 半径越大, 逃逸需要的迭代次数越多
+    assert_eq!(escape_time(&Mandelbrot, zero, Complex { re: 1.0, im: 0.0 }, 255, 100.0), Some(5));
+    assert_eq!(escape_time(&Mandelbrot, zero, Complex { re: -1.0, im: 0.0 }, 10_000, 2.0), None);
+    // 靠近边界的点需要超过 255 次迭代才能逃逸
+    assert_eq!(escape_time(&Mandelbrot, zero, Complex { re: 0.2501, im: 0.0 }, 255, 2.0), None);
+    assert!(escape_time(&Mandelbrot, zero, Complex { re: 0.2501, im: 0.0 }, 10_000, 2.0).unwrap() > 255);
+}
+
+#[test]
+fn test_escape_time_julia() {
+    // c = 0 时茹利亚集是单位圆盘: 圆内的点永远不会逃逸, 圆外的点会逃逸
+    let c = Complex { re: 0.0, im: 0.0 };
+    assert_eq!(escape_time(&Mandelbrot, Complex { re: 0.5, im: 0.5 }, c, 1000, 2.0), None);
+    assert_eq!(escape_time(&Mandelbrot, Complex { re: 1.5, im: 0.0 }, c, 1000, 2.0), Some(1));
+    assert_eq!(escape_time(&Mandelbrot, Complex { re: 1.01, im: 0.0 }, c, 1000, 2.0), Some(7));
+}
+
+/// 与 escape_time 相同, 但返回连续 (平滑) 的逃逸值, 用来消除着色时的色带
+///
+/// 逃逸时对最后的 z 做 log-log 归一化: n + 1 - log_d(log_R|z|), 其中 d 是公式的次数, R 是 escape_radius
+/// 结果被限制在 [0, limit] 之间
+/// 当 |z| 恰好等于 R 时结果为 n + 1, 等于 R^d 时结果为 n, 因此相邻的迭代次数之间是连续过渡的
+/// 如果没有逃逸, 则返回 None
+pub fn escape_time_smooth<F: Fractal + ?Sized>(
+    fractal: &F,
+    mut z: Complex<f64>,
+    c: Complex<f64>,
+    limit: usize,
+    escape_radius: f64,
+) -> Option<f64> {
+    let bailout = escape_radius * escape_radius;
+    for i in 0..limit {
+        if z.norm_sqr() > bailout {
+            return Some(smooth_escape(i, z, bailout, fractal.degree(), limit));
+        }
+        z = fractal.step(z, c);
+    }
+
+    None
+}
+
+/// 第 i 次迭代时 z 逃逸出半径平方为 bailout 的圆, 计算对应的平滑逃逸值, degree 是公式的次数
+fn smooth_escape(i: usize, z: Complex<f64>, bailout: f64, degree: f64, limit: usize) -> f64 {
+    // log_R|z| = ln(|z|^2) / ln(R^2)
+    let nu = (z.norm_sqr().ln() / bailout.ln()).log(degree);
+    (i as f64 + 1.0 - nu).clamp(0.0, limit as f64)
+}
+
+#[test]
+fn test_escape_time_smooth() {
+    let limit = 255;
+    let zero = Complex { re: 0.0, im: 0.0 };
+    // 集合内部的点和 escape_time 一样返回 None
+    assert_eq!(escape_time_smooth(&Mandelbrot, zero, Complex { re: -0.5, im: 0.0 }, limit, 2.0), None);
+    // 平滑值和整数迭代次数的差距不超过 1.5
+    for &(re, im) in &[(-2.1, 0.1), (0.3, 0.0), (0.5, 0.1), (1.0, 0.1), (-0.75, 0.2)] {
+        let c = Complex { re, im };
+        for &radius in &[2.0, 10.0] {
+            let count = escape_time(&Mandelbrot, zero, c, limit, radius).unwrap() as f64;
+            let smooth = escape_time_smooth(&Mandelbrot, zero, c, limit, radius).unwrap();
+            assert!((smooth - count).abs() <= 1.5, "{} vs {}", smooth, count);
+        }
+    }
+    // 高次公式按自己的次数归一化
+    let cubic = fractal::Multibrot { degree: 3 };
+    let c = Complex { re: 0.7, im: 0.3 };
+    let count = escape_time(&cubic, zero, c, limit, 2.0).unwrap() as f64;
+    let smooth = escape_time_smooth(&cubic, zero, c, limit, 2.0).unwrap();
+    assert!((smooth - count).abs() <= 1.5, "{} vs {}", smooth, count);
+    // 沿实轴靠近边界时, 平滑值单调增加
+    let a = escape_time_smooth(&Mandelbrot, zero, Complex { re: 0.30, im: 0.0 }, limit, 2.0).unwrap();
+    let b = escape_time_smooth(&Mandelbrot, zero, Complex { re: 0.29, im: 0.0 }, limit, 2.0).unwrap();
+    assert!(a < b);
+}
+
+/// 把字符串 s (形如 "400x600" 或 "1.0,0.5") 解析成一个坐标对
+///
+/// 字符串具有 <left><sep><right> 的格式, <left> 和 <right> 是可以被 T::From_str 解析的字符串
+/// 如果 s 具有正确的格式就返回 Ok((x,y)), 否则返回 RenderError::Parse, 指出是缺少分隔符还是哪一边无法解析
+pub fn parse_pair<T: FromStr>(s: &str, separator: char) -> Result<(T, T), RenderError>
+where
+    T::Err: std::fmt::Display,
+{
+    // ? 在出错时提前返回错误, 否则取出 Ok 中的值
+    let index = s
+        .find(separator)
+        .ok_or_else(|| RenderError::parse(s, s.len(), format!("expected '{}' between the two values", separator)))?;
+    // &s[..index] 和 &s[index+1..] 是字符串切片, 类型 T 的关联函数会 from_str 会将他们解析成类型 T 的值
+    let (left, right) = (&s[..index], &s[index + separator.len_utf8()..]);
+    let l = T::from_str(left).map_err(|error| RenderError::parse(s, 0, format!("invalid first value '{}': {}", left, error)))?;
+    let r = T::from_str(right)
+        .map_err(|error| RenderError::parse(s, index + separator.len_utf8(), format!("invalid second value '{}': {}", right, error)))?;
+    Ok((l, r))
+}
+
+#[test]
+fn test_parse_pair() {
+    assert_eq!(parse_pair::<i32>("", ',').ok(), None);
+    assert_eq!(parse_pair::<i32>("10,", ',').ok(), None);
+    assert_eq!(parse_pair::<i32>(",10", ',').ok(), None);
+    assert_eq!(parse_pair::<i32>("10,20", ',').ok(), Some((10, 20)));
+    assert_eq!(parse_pair::<i32>("10,20xy", ',').ok(), None);
+    assert_eq!(parse_pair::<f64>("0.5x", 'x').ok(), None);
+    assert_eq!(parse_pair::<f64>("0.5x1.5", 'x').ok(), Some((0.5, 1.5)));
+
+    // 错误指出出错的位置: 缺少分隔符时指向末尾, 否则指向无法解析的那一边
+    let position = |s: &str| match parse_pair::<i32>(s, ',') {
+        Err(RenderError::Parse { position, .. }) => position,
+        other => panic!("{:?}", other),
+    };
+    assert_eq!(position("1020"), 4);
+    assert_eq!(position("ten,20"), 0);
+    assert_eq!(position("10,twenty"), 3);
+}
+
+pub fn parse_complex(s: &str) -> Result<Complex<f64>, RenderError> {
+    parse_pair(s, ',').map(|(re, im)| Complex { re, im })
+}
+
+#[test]
+fn test_parse_complex() {
+    assert_eq!(
+        parse_complex("1.25,-0.0625").ok(),
+        Some(Complex {
+            re: 1.25,
+            im: -0.0625
+        })
+    );
+    assert_eq!(parse_complex(",-0.0625").ok(), None);
+}
+
+/// 给定输出图像中像素的行和列, 返回复平面中对应的坐标
+///
+/// bounds 定义了图像的像素宽度和像素高度
+/// pixel 表示图像中特定像素的 (column, row) 二元组
+/// upper_left 和 lower_right 定义了复平面中表示指定图像覆盖范围的点
+pub fn pixel_to_point(
+    bounds: (usize, usize),
+    pixel: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+) -> Complex<f64> {
+    let (width, height) = (
+        lower_right.re - upper_left.re,
+        upper_left.im - lower_right.im,
+    );
+    Complex {
+        re: upper_left.re + pixel.0 as f64 * width / bounds.0 as f64,
+        im: upper_left.im - pixel.1 as f64 * height / bounds.1 as f64,
+        // 这里用减法是因为在屏幕坐标系中 pixel.1 是向下递增的, 但复数的虚部是向上递增的
+    }
+}
+
+#[test]
+fn test_pixel_to_point() {
+    assert_eq!(
+        pixel_to_point(
+            (100, 100),
+            (25, 75),
+            Complex { re: -1.0, im: 1.0 },
+            Complex { re: 1.0, im: -1.0 }
+        ),
+        Complex { re: -0.5, im: -0.5 }
+    );
+}
+
+/// pixel_to_point 的逆映射: 求出复平面上的点落在哪个像素中, 点在图像之外时返回 None
+///
+/// 每个像素覆盖从它的左上角 (即 pixel_to_point 的结果) 开始的一个小矩形, 右边和下边的边界属于相邻的像素
+pub fn point_to_pixel(
+    bounds: (usize, usize),
+    point: Complex<f64>,
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+) -> Option<(usize, usize)> {
+    let column = (point.re - upper_left.re) / (lower_right.re - upper_left.re) * bounds.0 as f64;
+    let row = (upper_left.im - point.im) / (upper_left.im - lower_right.im) * bounds.1 as f64;
+    // NaN 与任何数比较的结果都是 false, 所以也会返回 None
+    if column >= 0.0 && row >= 0.0 && column < bounds.0 as f64 && row < bounds.1 as f64 {
+        Some((column as usize, row as usize))
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_point_to_pixel() {
+    let upper_left = Complex { re: -1.0, im: 1.0 };
+    let lower_right = Complex { re: 1.0, im: -1.0 };
+    assert_eq!(point_to_pixel((100, 200), Complex { re: -0.5, im: -0.5 }, upper_left, lower_right), Some((25, 150)));
+    assert_eq!(point_to_pixel((100, 200), upper_left, upper_left, lower_right), Some((0, 0)));
+    assert_eq!(point_to_pixel((100, 200), lower_right, upper_left, lower_right), None);
+    assert_eq!(point_to_pixel((100, 200), Complex { re: 0.0, im: 1.5 }, upper_left, lower_right), None);
+    assert_eq!(point_to_pixel((100, 200), Complex { re: f64::NAN, im: 0.0 }, upper_left, lower_right), None);
+}
+
+// 坐标映射的性质测试: 在随机的图像尺寸和视口下, pixel_to_point 和 point_to_pixel 互为逆映射
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn prop_pixel_round_trip(
+        bounds in (1usize..4000, 1usize..4000),
+        center in (-2.0..2.0f64, -2.0..2.0f64),
+        size in (-8.0..1.0f64, -8.0..1.0f64),
+        fraction in (0.0..1.0f64, 0.0..1.0f64),
+    ) {
+        let (width, height) = (10f64.powf(size.0), 10f64.powf(size.1));
+        let upper_left = Complex { re: center.0 - width / 2.0, im: center.1 + height / 2.0 };
+        let lower_right = Complex { re: center.0 + width / 2.0, im: center.1 - height / 2.0 };
+        let pixel = (
+            ((fraction.0 * bounds.0 as f64) as usize).min(bounds.0 - 1),
+            ((fraction.1 * bounds.1 as f64) as usize).min(bounds.1 - 1),
+        );
+        // 像素中心的点一定映射回这个像素
+        let point = pixel_to_point_rotated(bounds, pixel, (0.5, 0.5), upper_left, lower_right, None);
+        proptest::prop_assert_eq!(point_to_pixel(bounds, point, upper_left, lower_right), Some(pixel));
+    }
+
+    #[test]
+    fn prop_outside_is_none(
+        bounds in (1usize..4000, 1usize..4000),
+        center in (-2.0..2.0f64, -2.0..2.0f64),
+        size in (-8.0..1.0f64, -8.0..1.0f64),
+        outside in (0.001..10.0f64, -1.0..2.0f64),
+        side in 0usize..4,
+    ) {
+        let (width, height) = (10f64.powf(size.0), 10f64.powf(size.1));
+        let upper_left = Complex { re: center.0 - width / 2.0, im: center.1 + height / 2.0 };
+        let lower_right = Complex { re: center.0 + width / 2.0, im: center.1 - height / 2.0 };
+        // 先在某一条边之外取一点, 另一个方向上可以在图像范围内外任意取值
+        let along = (upper_left.re + outside.1 * width, lower_right.im + outside.1 * height);
+        let point = match side {
+            0 => Complex { re: upper_left.re - outside.0 * width, im: along.1 },
+            1 => Complex { re: lower_right.re + outside.0 * width, im: along.1 },
+            2 => Complex { re: along.0, im: upper_left.im + outside.0 * height },
+            _ => Complex { re: along.0, im: lower_right.im - outside.0 * height },
+        };
+        proptest::prop_assert_eq!(point_to_pixel(bounds, point, upper_left, lower_right), None);
+    }
+}
+
+/// 超采样时每个方向上最多的采样点数, 每个像素最多 MAX_SAMPLES^2 个采样点
+pub const MAX_SAMPLES: usize = 64;
+
+/// 渲染参数, 各个字段的默认值见 Default
+#[derive(Clone)]
+pub struct RenderOptions {
+    /// 迭代公式
+    pub fractal: Arc<dyn Fractal>,
+    pub palette: Palette,
+    /// 为 true 时使用 escape_time_smooth 计算连续的逃逸值
+    pub smooth: bool,
+    /// 最大迭代次数
+    pub limit: usize,
+    /// 逃逸半径, 必须是不小于 2 的有限值
+    pub escape_radius: f64,
+    /// 为 Some(c) 时渲染参数为 c 的茹利亚集, 否则渲染曼德博集
+    pub julia: Option<Complex<f64>>,
+    /// 为 true 且公式为 z^2 + c 时, 使用 simd 模块中的向量化内核一次迭代 simd::LANES 个点
+    pub simd: bool,
+    /// 视口的旋转, 每个像素对应的点在计算前都会先绕 pivot 旋转
+    pub rotation: Option<viewport::Rotation>,
+    /// 超采样: 每个像素在两个方向上各取 samples 个采样点, 为 1 时不做超采样, 最多 MAX_SAMPLES
+    pub samples: usize,
+    /// 为 true 时在每个采样格子内随机选取采样点 (抖动采样), 否则取格子的中心
+    pub jitter: bool,
+    /// 为 true 时用 interior 模块跳过主心形和周期 2 圆盘内的点, 并做周期检测, 结果与不检查时逐位一致
+    pub interior_check: bool,
+    /// 为 true 时按逃逸值的累积分布着色 (直方图均衡化), 见 equalize 模块
+    pub equalize: bool,
+    /// 渲染进度: 为 Some 时每渲染完一行就前进一个单位, 取消之后不再开始新的行
+    pub progress: Option<Arc<progress::Progress>>,
+}
+
+impl RenderOptions {
+    /// 渲染是否已经被取消, 取消之后还没有渲染的行保持原样
+    fn cancelled(&self) -> bool {
+        self.progress.as_ref().is_some_and(|progress| progress.is_cancelled())
+    }
+
+    /// 记录又渲染完了 rows 行
+    fn advance(&self, rows: usize) {
+        if let Some(progress) = &self.progress {
+            progress.advance(rows);
+        }
+    }
+
+    /// 记录有 rows 行已经提前完成, 不需要渲染
+    fn skip(&self, rows: usize) {
+        if let Some(progress) = &self.progress {
+            progress.skip(rows);
+        }
+    }
+
+    /// 检查各个字段能否用来渲染, 例如 limit 和 samples 不能为 0; 渲染函数本身只用断言检查这些条件
+    pub fn check(&self) -> Result<(), RenderError> {
+        let invalid = |option, value: &dyn std::fmt::Display, reason: &str| {
+            Err(RenderError::Options { option, value: value.to_string(), reason: reason.to_string() })
+        };
+        if self.limit == 0 {
+            return invalid("limit", &self.limit, "must be positive");
+        }
+        // 半径为无穷大时任何点都不会逃逸, 整幅图像都被当成集合内部
+        if !self.escape_radius.is_finite() {
+            return invalid("escape_radius", &self.escape_radius, "must be finite");
+        }
+        if self.escape_radius < 2.0 {
+            return invalid("escape_radius", &self.escape_radius, "must be at least 2");
+        }
+        if self.samples == 0 {
+            return invalid("samples", &self.samples, "must be positive");
+        }
+        if self.samples > MAX_SAMPLES {
+            return invalid("samples", &self.samples, &format!("must be at most {}", MAX_SAMPLES));
+        }
+        if self.equalize && (self.samples != 1 || self.jitter) {
+            return invalid("equalize", &self.equalize, "cannot be used with supersampling or jitter");
+        }
+        Ok(())
+    }
+
+    /// 检查 width 像素宽的一行的采样点能否放进内存; render_region 一次计算一整行的采样点
+    pub fn check_row(&self, width: usize) -> Result<(), RenderError> {
+        let bytes = self
+            .samples
+            .checked_mul(self.samples)
+            .and_then(|per_pixel| per_pixel.checked_mul(width))
+            .and_then(|points| points.checked_mul(std::mem::size_of::<Complex<f64>>()));
+        if bytes.is_none_or(|bytes| bytes > isize::MAX as usize) {
+            let reason = format!("too many samples for an image {} pixels wide", width);
+            return Err(RenderError::Options { option: "samples", value: self.samples.to_string(), reason });
+        }
+        Ok(())
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            fractal: Arc::new(Mandelbrot),
+            palette: Palette::Gray,
+            smooth: false,
+            limit: 255,
+            escape_radius: 2.0,
+            julia: None,
+            simd: false,
+            rotation: None,
+            samples: 1,
+            jitter: false,
+            interior_check: false,
+            equalize: false,
+            progress: None,
+        }
+    }
+}
+
+/// 考虑旋转和超采样的 pixel_to_point
+///
+/// offset 是采样点相对于像素左上角的偏移, 以像素为单位; 先求出未旋转时的点, 再把它绕 rotation.pivot 旋转
+/// 采样点的坐标直接由带小数的像素坐标 pixel + offset 算出, offset 为 0 时与 pixel_to_point 的结果逐位相同
+fn pixel_to_point_rotated(
+    bounds: (usize, usize),
+    pixel: (usize, usize),
+    offset: (f64, f64),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    rotation: Option<&Rotation>,
+) -> Complex<f64> {
+    let (width, height) = (lower_right.re - upper_left.re, upper_left.im - lower_right.im);
+    let point = Complex {
+        re: upper_left.re + (pixel.0 as f64 + offset.0) * width / bounds.0 as f64,
+        im: upper_left.im - (pixel.1 as f64 + offset.1) * height / bounds.1 as f64,
+    };
+    match rotation {
+        None => point,
+        Some(rotation) => rotation.apply(point),
+    }
+}
+
+#[test]
+fn test_pixel_to_point_rotated() {
+    let viewport = Viewport { center: Complex { re: 1.0, im: 1.0 }, zoom: 2.0, rotation: 90.0 };
+    let bounds = (100, 100);
+    let (upper_left, lower_right) = viewport.corners(bounds);
+    let rotation = viewport.rotation();
+    // 中心像素不受旋转影响
+    let center = pixel_to_point_rotated(bounds, (50, 50), (0.0, 0.0), upper_left, lower_right, rotation.as_ref());
+    assert!((center - viewport.center).norm() < 1e-12);
+    // 逆时针旋转 90 度后, 原本在左上角的点 (0, 2) 转到了左下角 (0, 0)
+    let corner = pixel_to_point_rotated(bounds, (0, 0), (0.0, 0.0), upper_left, lower_right, rotation.as_ref());
+    assert!((corner - Complex { re: 0.0, im: 0.0 }).norm() < 1e-12, "{}", corner);
+    // 不旋转也没有偏移时与 pixel_to_point 完全一致
+    assert_eq!(
+        pixel_to_point_rotated(bounds, (25, 75), (0.0, 0.0), upper_left, lower_right, None),
+        pixel_to_point(bounds, (25, 75), upper_left, lower_right)
+    );
+    // 像素 (25, 75) 的中心位于它和 (26, 76) 左上角的正中间
+    let middle = pixel_to_point_rotated(bounds, (25, 75), (0.5, 0.5), upper_left, lower_right, None);
+    let next = pixel_to_point(bounds, (26, 76), upper_left, lower_right);
+    let this = pixel_to_point(bounds, (25, 75), upper_left, lower_right);
+    assert!((middle - (this + next) / 2.0).norm() < 1e-12);
+}
+
+/// 将 options.fractal 对应的曼德博集 (或茹利亚集) 的矩形渲染到像素缓冲区中
+///
+/// bounds 参数会给出缓冲区 pixels 的宽度和高度, 每个像素占用 options.palette.channels() 个字节
+/// upper_left 和 lower_right 分别指定了复平面中的左上角和右下角的坐标 (旋转之前)
+/// 逃逸值会除以 options.limit 归一化到 [0, 1], 再交给调色板着色
+/// 超采样时每个像素计算 options.samples^2 个采样点, 像素的颜色是这些采样点颜色的平均值
+pub fn render(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    options: &RenderOptions,
+) {
+    render_region(pixels, bounds, ((0, 0), bounds), upper_left, lower_right, options);
+}
+
+/// 只渲染整幅图像中的一个矩形区域, 例如一行或者一块; region 是区域的 (左上角像素, 宽和高)
+///
+/// pixels 只容纳这个区域, bounds, upper_left 和 lower_right 仍然描述整幅图像
+/// 每个采样点都按整幅图像的几何关系计算, 所以分行或分块渲染的结果与一次渲染整幅图像逐位一致
+pub fn render_region(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    region: ((usize, usize), (usize, usize)),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    options: &RenderOptions,
+) {
+    let ((left, top), size) = region;
+    let channels = options.palette.channels();
+    assert!(pixels.len() == size.0 * size.1 * channels);
+    assert!(left + size.0 <= bounds.0 && top + size.1 <= bounds.1);
+
+    let grid = supersample::sample_offsets(options.samples, None);
+    let per_pixel = grid.len();
+    let mut points = Vec::with_capacity(size.0.checked_mul(per_pixel).expect("too many samples in a row"));
+
+    // 遍历区域中所有的像素点, row 和 column 是区域内的坐标, pixel 是整幅图像中的坐标
+    for row in 0..size.1 {
+        if options.cancelled() {
+            return;
+        }
+        // 先求出这一行所有采样点的坐标, 再一次性计算它们的逃逸值
+        points.clear();
+        for column in 0..size.0 {
+            let pixel = (left + column, top + row);
+            let jittered;
+            let offsets = if options.jitter {
+                let corner = pixel_to_point(bounds, pixel, upper_left, lower_right);
+                let seed = supersample::pixel_seed(corner.re, corner.im);
+                jittered = supersample::sample_offsets(options.samples, Some(seed));
+                &jittered
+            } else {
+                &grid
+            };
+            for &offset in offsets {
+                let rotation = options.rotation.as_ref();
+                points.push(pixel_to_point_rotated(bounds, pixel, offset, upper_left, lower_right, rotation));
+            }
+        }
+        let escapes = escape_values(&points, options);
+
+        for (column, samples) in escapes.chunks(per_pixel).enumerate() {
+            let offset = (row * size.0 + column) * channels;
+            paint_average(&mut pixels[offset..offset + channels], samples, options);
+        }
+        options.advance(1);
+    }
+}
+
+/// 把一个像素内所有采样点的颜色取平均值后写入 pixel, 只有一个采样点时直接着色
+fn paint_average(pixel: &mut [u8], samples: &[Option<f64>], options: &RenderOptions) {
+    let t = |escape: Option<f64>| escape.map(|value| value / options.limit as f64);
+    if let [escape] = samples {
+        options.palette.paint(pixel, t(*escape));
+        return;
+    }
+
+    let mut sum = [0u32; 3];
+    let mut color = [0u8; 3];
+    for &escape in samples {
+        options.palette.paint(&mut color[..pixel.len()], t(escape));
+        for (sum, &channel) in sum.iter_mut().zip(&color) {
+            *sum += channel as u32;
+        }
+    }
+    let count = samples.len() as u32;
+    for (channel, &sum) in pixel.iter_mut().zip(&sum) {
+        // 四舍五入
+        *channel = ((sum + count / 2) / count) as u8;
+    }
+}
+
+/// 用 threads 个线程计算每个像素左上角的逃逸值而不着色, 用于由逃逸值生成的输出格式
+pub fn render_escapes(
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    options: &RenderOptions,
+    threads: usize,
+) -> output::EscapeMap {
+    let mut escapes = vec![None; bounds.0 * bounds.1];
+    parallel::render_rows_parallel(&mut escapes, bounds.0, threads, |row, line| {
+        if options.cancelled() {
+            return;
+        }
+        let rotation = options.rotation.as_ref();
+        let points: Vec<_> = (0..bounds.0)
+            .map(|column| pixel_to_point_rotated(bounds, (column, row), (0.0, 0.0), upper_left, lower_right, rotation))
+            .collect();
+        line.copy_from_slice(&escape_values(&points, options));
+        options.advance(1);
+    });
+    output::EscapeMap { bounds, upper_left, lower_right, limit: options.limit, escapes }
+}
+
+#[test]
+fn test_render_escapes() {
+    // 逃逸值着色后与 render 的结果相同
+    let bounds = (41, 30);
+    let upper_left = Complex { re: -2.0, im: 1.2 };
+    let lower_right = Complex { re: 1.0, im: -1.2 };
+    let options = RenderOptions { smooth: true, palette: Palette::Ocean, ..RenderOptions::default() };
+    let map = render_escapes(bounds, upper_left, lower_right, &options, 3);
+    let mut pixels = vec![0; bounds.0 * bounds.1 * 3];
+    render(&mut pixels, bounds, upper_left, lower_right, &options);
+    assert_eq!(map.paint(&options.palette), pixels);
+}
+
+/// 计算一组点的逃逸值, 可以使用向量化内核时每次计算 simd::LANES 个点
+fn escape_values(points: &[Complex<f64>], options: &RenderOptions) -> Vec<Option<f64>> {
+    if !(options.simd && options.fractal.is_quadratic()) {
+        return points.iter().map(|&point| escape_value(point, options)).collect();
+    }
+
+    // 向量化内核不做周期检测, 只把主心形和周期 2 圆盘内的点提前排除, 其余的点照常分组计算
+    if options.interior_check && options.julia.is_none() {
+        let outside: Vec<_> = points.iter().copied().filter(|&c| !interior::in_cardioid_or_bulb(c)).collect();
+        let mut outside_escapes = escape_values_simd(&outside, options).into_iter();
+        return points
+            .iter()
+            .map(|&c| if interior::in_cardioid_or_bulb(c) { None } else { outside_escapes.next().unwrap() })
+            .collect();
+    }
+    escape_values_simd(points, options)
+}
+
+/// 用向量化内核计算一组点的逃逸值, 每次计算 simd::LANES 个点
+fn escape_values_simd(points: &[Complex<f64>], options: &RenderOptions) -> Vec<Option<f64>> {
+    let mut escapes = Vec::with_capacity(points.len());
+    for group in points.chunks(simd::LANES) {
+        // 最后一组不足 LANES 个点时用最后一个点补齐, 补齐的结果会被丢弃
+        let lanes = std::array::from_fn(|lane| group[lane.min(group.len() - 1)]);
+        escapes.extend_from_slice(&simd::escape_values(lanes, options)[..group.len()]);
+    }
+    escapes
+}
+
+/// 计算复平面上一个点的逃逸值: 逃逸所需的迭代次数 (平滑模式下是连续值), 没有逃逸则返回 None
+fn escape_value(point: Complex<f64>, options: &RenderOptions) -> Option<f64> {
+    let (z, c) = match options.julia {
+        None => (Complex { re: 0.0, im: 0.0 }, point),
+        Some(c) => (point, c),
+    };
+    if options.interior_check {
+        if options.julia.is_none() && options.fractal.is_quadratic() && interior::in_cardioid_or_bulb(c) {
+            return None;
+        }
+        let bailout = options.escape_radius * options.escape_radius;
+        return interior::escape_time_periodic(&*options.fractal, z, c, options.limit, options.escape_radius).map(|(i, z)| {
+            if options.smooth {
+                smooth_escape(i, z, bailout, options.fractal.degree(), options.limit)
+            } else {
+                i as f64
+            }
+        });
+    }
+    if options.smooth {
+        escape_time_smooth(&*options.fractal, z, c, options.limit, options.escape_radius)
+    } else {
+        escape_time(&*options.fractal, z, c, options.limit, options.escape_radius).map(|count| count as f64)
+    }
+}
+
+#[test]
+fn test_render_gray() {
+    // 左边的像素位于 -2.5 处, 第一次迭代后就逃逸了, 右边的像素位于集合内部
+    let mut pixels = [0u8; 2];
+    let upper_left = Complex { re: -2.5, im: 0.0 };
+    let lower_right = Complex { re: 0.5, im: -0.1 };
+    render(&mut pixels, (2, 1), upper_left, lower_right, &RenderOptions::default());
+    assert_eq!(pixels, [254, 0]);
+
+    // 灰度按迭代上限缩放, 上限越大, 同样的迭代次数对应的灰度越亮
+    let options = RenderOptions { limit: 1020, ..RenderOptions::default() };
+    render(&mut pixels, (2, 1), upper_left, lower_right, &options);
+    assert_eq!(pixels, [255, 0]);
+
+    // c = 0 的茹利亚集: -2.5 处第一次检查就已经逃逸, 0.5 位于单位圆盘内
+    let options = RenderOptions { julia: Some(Complex { re: 0.0, im: 0.0 }), ..RenderOptions::default() };
+    render(&mut pixels, (2, 1), upper_left, lower_right, &options);
+    assert_eq!(pixels, [255, 0]);
+}
+
+#[test]
+fn test_render_simd() {
+    // 向量化内核渲染出的图像必须与逐点计算的结果逐像素一致, 宽度故意不是 LANES 的整数倍
+    let bounds = (61, 40);
+    let upper_left = Complex { re: -2.0, im: 1.2 };
+    let lower_right = Complex { re: 1.0, im: -1.2 };
+    let variants = [
+        RenderOptions::default(),
+        RenderOptions { smooth: true, palette: Palette::Fire, ..RenderOptions::default() },
+        RenderOptions { julia: Some(Complex { re: -0.8, im: 0.156 }), limit: 1000, ..RenderOptions::default() },
+        RenderOptions { escape_radius: 50.0, smooth: true, ..RenderOptions::default() },
+        RenderOptions { samples: 3, jitter: true, palette: Palette::Hsv, ..RenderOptions::default() },
+    ];
+    for scalar in variants {
+        let mut expected = vec![0; bounds.0 * bounds.1 * scalar.palette.channels()];
+        render(&mut expected, bounds, upper_left, lower_right, &scalar);
+        let vectorized = RenderOptions { simd: true, ..scalar };
+        let mut pixels = vec![0; expected.len()];
+        render(&mut pixels, bounds, upper_left, lower_right, &vectorized);
+        assert_eq!(pixels, expected);
+    }
+}
+
+#[test]
+fn test_render_progress() {
+    let bounds = (30, 20);
+    let upper_left = Complex { re: -2.0, im: 1.2 };
+    let lower_right = Complex { re: 1.0, im: -1.2 };
+    for backend in parallel::Backend::ALL {
+        let progress = Arc::new(progress::Progress::new(bounds.1));
+        let options = RenderOptions { progress: Some(Arc::clone(&progress)), ..RenderOptions::default() };
+        let mut pixels = vec![0; bounds.0 * bounds.1];
+        backend.render(&mut pixels, bounds, upper_left, lower_right, &options, 3).unwrap();
+        assert_eq!(progress.fraction(), 1.0, "{}", backend.name());
+    }
+
+    // 取消之后不再开始新的行, 已经写入缓冲区的内容保持不变
+    let progress = Arc::new(progress::Progress::new(bounds.1));
+    progress.cancel();
+    let options = RenderOptions { progress: Some(Arc::clone(&progress)), ..RenderOptions::default() };
+    let mut pixels = vec![7; bounds.0 * bounds.1];
+    parallel::render_parallel(&mut pixels, bounds, ((0, 0), bounds), upper_left, lower_right, &options, 3);
+    assert!(pixels.iter().all(|&pixel| pixel == 7));
+    assert_eq!(progress.fraction(), 0.0);
+}
+
+#[test]
+fn test_render_supersampling() {
+    // 1x1 的图像, 2x2 的采样点位于 (-2, ±0.5) 和 (0, ±0.5)
+    // 前两个点第一次迭代后就逃逸了 (灰度 254), 后两个点位于集合内部 (灰度 0), 平均值为 127
+    let mut pixel = [0u8];
+    let upper_left = Complex { re: -3.0, im: 1.0 };
+    let lower_right = Complex { re: 1.0, im: -1.0 };
+    let options = RenderOptions { samples: 2, ..RenderOptions::default() };
+    render(&mut pixel, (1, 1), upper_left, lower_right, &options);
+    assert_eq!(pixel, [127]);
+
+    // 抖动采样的结果是确定的, 与渲染时的分块方式无关
+    let bounds = (40, 30);
+    let options = RenderOptions { samples: 2, jitter: true, ..RenderOptions::default() };
+    let (upper_left, lower_right) = (Complex { re: -2.0, im: 1.2 }, Complex { re: 1.0, im: -1.2 });
+    let mut whole = vec![0; bounds.0 * bounds.1];
+    render(&mut whole, bounds, upper_left, lower_right, &options);
+    let mut rows = vec![0; bounds.0 * bounds.1];
+    parallel::render_parallel(&mut rows, bounds, ((0, 0), bounds), upper_left, lower_right, &options, 3);
+    assert_eq!(whole, rows);
+}
+
+#[test]
+fn test_render_supersampling_deep() {
+    // 放大后像素很小, 如果各个后端按行或按块重新计算像素的大小, 舍入误差会让采样点落到不同的位置
+    let bounds = (30, 20);
+    let upper_left = Complex { re: -0.7436447, im: 0.1318262 };
+    let lower_right = Complex { re: -0.7436433, im: 0.1318252 };
+    for samples in [2, 3] {
+        let options = RenderOptions { samples, limit: 3000, ..RenderOptions::default() };
+        let mut expected = vec![0; bounds.0 * bounds.1];
+        render(&mut expected, bounds, upper_left, lower_right, &options);
+        for backend in parallel::Backend::ALL {
+            let mut pixels = vec![0; expected.len()];
+            backend.render(&mut pixels, bounds, upper_left, lower_right, &options, 3).unwrap();
+            assert!(pixels == expected, "{} with {} samples", backend.name(), samples);
+        }
+    }
+}
+
+#[test]
+fn test_render_interior_check() {
+    // 内部检查只是加速, 渲染出的图像必须与普通的循环逐像素一致
+    let bounds = (61, 40);
+    let upper_left = Complex { re: -2.0, im: 1.2 };
+    let lower_right = Complex { re: 1.0, im: -1.2 };
+    let variants = [
+        RenderOptions { limit: 2000, ..RenderOptions::default() },
+        RenderOptions { smooth: true, palette: Palette::Fire, ..RenderOptions::default() },
+        RenderOptions { simd: true, limit: 1000, ..RenderOptions::default() },
+        RenderOptions { simd: true, smooth: true, samples: 2, ..RenderOptions::default() },
+        RenderOptions { julia: Some(Complex { re: -0.8, im: 0.156 }), limit: 1000, ..RenderOptions::default() },
+        RenderOptions { fractal: Arc::new(fractal::Tricorn), ..RenderOptions::default() },
+    ];
+    for plain in variants {
+        let mut expected = vec![0; bounds.0 * bounds.1 * plain.palette.channels()];
+        render(&mut expected, bounds, upper_left, lower_right, &plain);
+        let checked = RenderOptions { interior_check: true, ..plain };
+        let mut pixels = vec![0; expected.len()];
+        render(&mut pixels, bounds, upper_left, lower_right, &checked);
+        assert_eq!(pixels, expected);
+    }
+}
+
+/// text 中的每一项都作为一个 tEXt 块写入 PNG 文件, 通常来自 metadata::RenderRecord::to_text
+pub fn write_image(filename: &str, pixels: &[u8], bounds: (usize, usize), color_type: ColorType, text: &[(String, String)]) -> Result <(), std::io::Error>{
+ let output = std::io::BufWriter::new(File::create(filename)?);
+ encode_png(output, pixels, bounds, color_type, text)
+}
+
+/// 把 8 位的像素编码成 PNG 写入 output, 例如写入内存中的缓冲区
+pub fn encode_png<W: std::io::Write>(output: W, pixels: &[u8], bounds: (usize, usize), color_type: ColorType, text: &[(String, String)]) -> Result <(), std::io::Error>{
+ let color = match color_type {
+  ColorType::Gray(8) => png::ColorType::Grayscale,
+  ColorType::RGB(8) => png::ColorType::Rgb,
+  other => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("unsupported color type {:?}", other))),
+ };
+ let mut encoder = png::Encoder::new(output, bounds.0 as u32, bounds.1 as u32);
+ encoder.set_color(color);
+ encoder.set_depth(png::BitDepth::Eight);
+ for (keyword, value) in text {
+  encoder.add_text_chunk(keyword.clone(), value.clone())?;
+ }
+ let mut writer = encoder.write_header()?;
+ writer.write_image_data(pixels)?;
+ writer.finish()?;
+ Ok(())
+}
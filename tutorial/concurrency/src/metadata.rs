@@ -0,0 +1,109 @@
+//! 把渲染参数作为 PNG 的 tEXt 块写入图像, 之后可以从图像中读回, 重新渲染出同样的画面或者提高分辨率
+
+use num::Complex;
+use std::fs::File;
+use std::io;
+
+/// 重新渲染一幅图像所需的全部参数, 角点和选项的写法与命令行参数相同
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderRecord {
+    pub bounds: (usize, usize),
+    /// 复平面上未旋转的左上角和右下角, 深度缩放时保留原始的高精度写法
+    pub upper_left: String,
+    pub lower_right: String,
+    /// 绕两个角点的中心逆时针旋转的角度
+    pub rotate: f64,
+    pub limit: usize,
+    /// 其余影响渲染结果的命令行选项, 例如 "--palette=fire --smooth"
+    pub options: String,
+}
+
+impl RenderRecord {
+    /// 换成另一组 f64 角点的记录, 例如动画中的每一帧
+    pub fn with_corners(&self, upper_left: Complex<f64>, lower_right: Complex<f64>) -> RenderRecord {
+        RenderRecord {
+            upper_left: format!("{},{}", upper_left.re, upper_left.im),
+            lower_right: format!("{},{}", lower_right.re, lower_right.im),
+            ..self.clone()
+        }
+    }
+
+    /// tEXt 块的关键字和内容
+    pub fn to_text(&self) -> Vec<(String, String)> {
+        [
+            ("Pixels", format!("{}x{}", self.bounds.0, self.bounds.1)),
+            ("UpperLeft", self.upper_left.clone()),
+            ("LowerRight", self.lower_right.clone()),
+            ("Rotate", self.rotate.to_string()),
+            ("Limit", self.limit.to_string()),
+            ("Options", self.options.clone()),
+        ]
+        .into_iter()
+        .map(|(keyword, text)| (keyword.to_string(), text))
+        .collect()
+    }
+
+    /// 从 tEXt 块中恢复记录, 缺少任何一项或者无法解析时返回 None
+    pub fn from_text(text: &[(String, String)]) -> Option<RenderRecord> {
+        let field = |keyword: &str| text.iter().find(|(key, _)| key == keyword).map(|(_, value)| value.clone());
+        Some(RenderRecord {
+            bounds: crate::parse_pair(&field("Pixels")?, 'x').ok()?,
+            upper_left: field("UpperLeft")?,
+            lower_right: field("LowerRight")?,
+            rotate: field("Rotate")?.parse().ok()?,
+            limit: field("Limit")?.parse().ok()?,
+            options: field("Options")?,
+        })
+    }
+}
+
+#[test]
+fn test_record_text() {
+    let record = RenderRecord {
+        bounds: (800, 600),
+        upper_left: "-1.20,0.35".to_string(),
+        lower_right: "-1,0.20".to_string(),
+        rotate: 30.0,
+        limit: 1000,
+        options: "--palette=fire --smooth".to_string(),
+    };
+    let text = record.to_text();
+    assert_eq!(text[0], ("Pixels".to_string(), "800x600".to_string()));
+    assert_eq!(RenderRecord::from_text(&text), Some(record.clone()));
+    assert_eq!(RenderRecord::from_text(&text[1..]), None);
+
+    let frame = record.with_corners(Complex { re: -2.0, im: 0.1 }, Complex { re: 0.5, im: -0.1 });
+    assert_eq!((frame.upper_left.as_str(), frame.lower_right.as_str()), ("-2,0.1", "0.5,-0.1"));
+    assert_eq!(frame.limit, 1000);
+}
+
+/// 读取 PNG 文件中所有未压缩的 tEXt 块
+pub fn read_png_text(filename: &str) -> io::Result<Vec<(String, String)>> {
+    let reader = png::Decoder::new(File::open(filename)?).read_info()?;
+    Ok(reader
+        .info()
+        .uncompressed_latin1_text
+        .iter()
+        .map(|chunk| (chunk.keyword.clone(), chunk.text.clone()))
+        .collect())
+}
+
+#[test]
+fn test_png_text_round_trip() {
+    use image::ColorType;
+
+    let record = RenderRecord {
+        bounds: (2, 1),
+        upper_left: "-1e-30,0.5".to_string(),
+        lower_right: "1e-30,0.49".to_string(),
+        rotate: 0.0,
+        limit: 255,
+        options: "--deep".to_string(),
+    };
+    let path = std::env::temp_dir().join(format!("metadata-test-{}.png", std::process::id()));
+    let filename = path.to_str().unwrap();
+    crate::write_image(filename, &[0, 255], (2, 1), ColorType::Gray(8), &record.to_text()).unwrap();
+    let text = read_png_text(filename).unwrap();
+    assert_eq!(RenderRecord::from_text(&text), Some(record));
+    std::fs::remove_file(filename).unwrap();
+}
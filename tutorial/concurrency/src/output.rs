@@ -0,0 +1,291 @@
+//! 把渲染结果写成不同格式的文件
+//!
+//! 8 位的 PNG, PPM/PGM 和 TIFF 保存着色后的像素; 16 位灰度 PNG 和原始逃逸值文件直接由逃逸值生成,
+//! 其中原始逃逸值文件还记录了图像的尺寸, 复平面上的角点和迭代上限, 之后可以用不同的调色板重新着色而不必重新计算
+
+use crate::palette::Palette;
+use crate::{parse_complex, parse_pair, write_image};
+use num::Complex;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// 8 位灰度或 RGB 的 PNG
+    Png,
+    /// 16 位灰度 PNG, 比 8 位的灰度多出 256 倍的层次
+    Png16,
+    /// 二进制的 PGM (灰度) 或 PPM (RGB), 由调色板的通道数决定
+    Pnm,
+    /// 未压缩的 8 位灰度或 RGB TIFF
+    Tiff,
+    /// 原始逃逸值文件, 见 write_raw
+    Raw,
+}
+
+impl Format {
+    pub const ALL: [Format; 5] = [Format::Png, Format::Png16, Format::Pnm, Format::Tiff, Format::Raw];
+
+    pub fn from_name(name: &str) -> Option<Format> {
+        Format::ALL.iter().copied().find(|format| format.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Png16 => "png16",
+            Format::Pnm => "pnm",
+            Format::Tiff => "tiff",
+            Format::Raw => "raw",
+        }
+    }
+
+    /// 根据文件的扩展名选择格式, 不认识的扩展名返回 None
+    ///
+    /// .png 总是对应 8 位的 PNG, 16 位的 PNG 需要用 --format=png16 指定
+    pub fn from_filename(filename: &str) -> Option<Format> {
+        let extension = filename.rsplit_once('.')?.1.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(Format::Png),
+            "ppm" | "pgm" | "pnm" => Some(Format::Pnm),
+            "tif" | "tiff" => Some(Format::Tiff),
+            "raw" => Some(Format::Raw),
+            _ => None,
+        }
+    }
+
+    /// 为 true 时这种格式由逃逸值生成, 需要用 write_escapes 写入
+    pub fn stores_escapes(&self) -> bool {
+        matches!(self, Format::Png16 | Format::Raw)
+    }
+}
+
+#[test]
+fn test_format_from_filename() {
+    assert_eq!(Format::from_filename("mandel.png"), Some(Format::Png));
+    assert_eq!(Format::from_filename("out/mandel.PGM"), Some(Format::Pnm));
+    assert_eq!(Format::from_filename("mandel.tif"), Some(Format::Tiff));
+    assert_eq!(Format::from_filename("mandel.raw"), Some(Format::Raw));
+    assert_eq!(Format::from_filename("mandel.jpg"), None);
+    assert_eq!(Format::from_filename("mandel"), None);
+    for format in Format::ALL {
+        assert_eq!(Format::from_name(format.name()), Some(format));
+    }
+}
+
+/// 每个像素的逃逸值, 以及计算它们时的视口和迭代上限
+#[derive(Debug, Clone, PartialEq)]
+pub struct EscapeMap {
+    pub bounds: (usize, usize),
+    pub upper_left: Complex<f64>,
+    pub lower_right: Complex<f64>,
+    pub limit: usize,
+    /// 按行优先排列, None 表示没有逃逸
+    pub escapes: Vec<Option<f64>>,
+}
+
+impl EscapeMap {
+    /// 用调色板给每个像素着色, 与不做超采样时 render 的着色方式相同
+    pub fn paint(&self, palette: &Palette) -> Vec<u8> {
+        self.paint_by(palette, |value| value / self.limit as f64)
+    }
+
+    /// 用调色板给每个像素着色, t 把逃逸值映射到调色板上的位置 [0, 1]
+    pub fn paint_by<T: Fn(f64) -> f64>(&self, palette: &Palette, t: T) -> Vec<u8> {
+        let channels = palette.channels();
+        let mut pixels = vec![0; self.escapes.len() * channels];
+        for (pixel, escape) in pixels.chunks_mut(channels).zip(&self.escapes) {
+            palette.paint(pixel, escape.map(&t));
+        }
+        pixels
+    }
+}
+
+/// 把着色后的像素写成 format 格式的文件, 不支持由逃逸值生成的格式
+///
+/// text 是写入 PNG tEXt 块的渲染参数, 其他格式会忽略它
+pub fn write_pixels(
+    filename: &str,
+    format: Format,
+    pixels: &[u8],
+    bounds: (usize, usize),
+    palette: &Palette,
+    text: &[(String, String)],
+) -> io::Result<()> {
+    match format {
+        Format::Png => write_image(filename, pixels, bounds, palette.color_type(), text),
+        Format::Pnm => write_pnm(filename, pixels, bounds, palette.channels()),
+        Format::Tiff => write_tiff(filename, pixels, bounds, palette.channels()),
+        Format::Png16 | Format::Raw => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the {} format is written from escape values, not pixels", format.name()),
+        )),
+    }
+}
+
+/// 把逃逸值写成 format 格式的文件, 保存像素的格式先用调色板着色
+pub fn write_escapes(
+    filename: &str,
+    format: Format,
+    map: &EscapeMap,
+    palette: &Palette,
+    text: &[(String, String)],
+) -> io::Result<()> {
+    match format {
+        Format::Png16 => write_png16(filename, map, text),
+        Format::Raw => write_raw(filename, map),
+        _ => write_pixels(filename, format, &map.paint(palette), map.bounds, palette, text),
+    }
+}
+
+/// 二进制的 PGM (P5) 或 PPM (P6): 一个文本的文件头, 后面紧跟着所有像素的字节
+fn write_pnm(filename: &str, pixels: &[u8], bounds: (usize, usize), channels: usize) -> io::Result<()> {
+    let mut output = BufWriter::new(File::create(filename)?);
+    let magic = if channels == 1 { "P5" } else { "P6" };
+    write!(output, "{}\n{} {}\n255\n", magic, bounds.0, bounds.1)?;
+    output.write_all(pixels)?;
+    output.flush()
+}
+
+fn write_tiff(filename: &str, pixels: &[u8], bounds: (usize, usize), channels: usize) -> io::Result<()> {
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    let mut encoder = TiffEncoder::new(BufWriter::new(File::create(filename)?)).map_err(io::Error::other)?;
+    let (width, height) = (bounds.0 as u32, bounds.1 as u32);
+    if channels == 1 {
+        encoder.write_image::<colortype::Gray8>(width, height, pixels)
+    } else {
+        encoder.write_image::<colortype::RGB8>(width, height, pixels)
+    }
+    .map_err(io::Error::other)
+}
+
+/// 16 位灰度 PNG, 与 Palette::Gray 一样越快逃逸越亮, 没有逃逸的点是黑色
+fn write_png16(filename: &str, map: &EscapeMap, text: &[(String, String)]) -> io::Result<()> {
+    let mut data = Vec::with_capacity(map.escapes.len() * 2);
+    for escape in &map.escapes {
+        let level = match escape {
+            None => 0,
+            Some(value) => 65535 - ((value / map.limit as f64).clamp(0.0, 1.0) * 65535.0).round() as u16,
+        };
+        // PNG 中的 16 位采样是大端序的
+        data.extend_from_slice(&level.to_be_bytes());
+    }
+
+    let output = BufWriter::new(File::create(filename)?);
+    let mut encoder = png::Encoder::new(output, map.bounds.0 as u32, map.bounds.1 as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Sixteen);
+    for (keyword, value) in text {
+        encoder.add_text_chunk(keyword.clone(), value.clone())?;
+    }
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// 原始逃逸值文件的第一行
+const RAW_MAGIC: &str = "mandelbrot-escapes 1";
+
+/// 原始逃逸值文件: 先是五行文本, 依次是 RAW_MAGIC, 图像尺寸, 左上角, 右下角和迭代上限,
+/// 格式与命令行参数相同; 后面是按行优先排列的逃逸值, 每个都是小端序的 f64, 没有逃逸的点记为 -1
+fn write_raw(filename: &str, map: &EscapeMap) -> io::Result<()> {
+    let mut output = BufWriter::new(File::create(filename)?);
+    writeln!(output, "{}", RAW_MAGIC)?;
+    writeln!(output, "{}x{}", map.bounds.0, map.bounds.1)?;
+    writeln!(output, "{},{}", map.upper_left.re, map.upper_left.im)?;
+    writeln!(output, "{},{}", map.lower_right.re, map.lower_right.im)?;
+    writeln!(output, "{}", map.limit)?;
+    for escape in &map.escapes {
+        output.write_all(&escape.unwrap_or(-1.0).to_le_bytes())?;
+    }
+    output.flush()
+}
+
+/// 读取 write_raw 写出的原始逃逸值文件
+pub fn read_raw(filename: &str) -> io::Result<EscapeMap> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: bad {}", filename, what));
+    let mut input = BufReader::new(File::open(filename)?);
+    let mut lines = Vec::new();
+    for _ in 0..5 {
+        let mut line = String::new();
+        input.read_line(&mut line)?;
+        lines.push(line.trim_end().to_string());
+    }
+    if lines[0] != RAW_MAGIC {
+        return Err(invalid("header"));
+    }
+    let bounds: (usize, usize) = parse_pair(&lines[1], 'x').map_err(|_| invalid("image dimensions"))?;
+    let upper_left = parse_complex(&lines[2]).map_err(|_| invalid("upper left corner"))?;
+    let lower_right = parse_complex(&lines[3]).map_err(|_| invalid("lower right corner"))?;
+    let limit = lines[4].parse().map_err(|_| invalid("iteration limit"))?;
+
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    // 尺寸来自文件头, 可能大得离谱, 乘积溢出时同样是损坏的文件
+    let length = bounds.0.checked_mul(bounds.1).and_then(|count| count.checked_mul(8));
+    if length != Some(data.len()) {
+        return Err(invalid("escape data length"));
+    }
+    let escapes = data
+        .chunks(8)
+        .map(|bytes| {
+            let value = f64::from_le_bytes(bytes.try_into().unwrap());
+            if value < 0.0 { None } else { Some(value) }
+        })
+        .collect();
+    Ok(EscapeMap { bounds, upper_left, lower_right, limit, escapes })
+}
+
+#[test]
+fn test_write_formats() {
+    let dir = std::env::temp_dir();
+    let path = |name: &str| dir.join(format!("output-test-{}-{}", std::process::id(), name)).to_str().unwrap().to_string();
+    let map = EscapeMap {
+        bounds: (3, 2),
+        upper_left: Complex { re: -2.0, im: 1.0 / 3.0 },
+        lower_right: Complex { re: 0.1, im: -1.0 },
+        limit: 100,
+        escapes: vec![Some(0.0), Some(1.5), None, Some(100.0), Some(50.0), None],
+    };
+
+    // 原始逃逸值文件可以原样读回, 包括角点的每一位
+    let raw = path("map.raw");
+    write_escapes(&raw, Format::Raw, &map, &Palette::Gray, &[]).unwrap();
+    assert_eq!(read_raw(&raw).unwrap(), map);
+    // 文件头中的尺寸乘起来会溢出时报告数据错误, 而不是 panic 或者回绕
+    let huge = path("huge.raw");
+    std::fs::write(&huge, format!("{}\n{}x{}\n-2,1\n1,-1\n100\n", RAW_MAGIC, usize::MAX, 2)).unwrap();
+    assert_eq!(read_raw(&huge).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    let pgm = path("map.pgm");
+    write_escapes(&pgm, Format::Pnm, &map, &Palette::Gray, &[]).unwrap();
+    let mut expected = b"P5\n3 2\n255\n".to_vec();
+    expected.extend_from_slice(&map.paint(&Palette::Gray));
+    assert_eq!(std::fs::read(&pgm).unwrap(), expected);
+
+    let png16 = path("map.png");
+    write_escapes(&png16, Format::Png16, &map, &Palette::Gray, &[]).unwrap();
+    let mut reader = png::Decoder::new(File::open(&png16).unwrap()).read_info().unwrap();
+    let mut data = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut data).unwrap();
+    assert_eq!(reader.info().bit_depth, png::BitDepth::Sixteen);
+    let levels: Vec<u16> = data.chunks(2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]])).collect();
+    assert_eq!(levels, [65535, 64552, 0, 0, 32767, 0]);
+
+    let tif = path("map.tif");
+    write_escapes(&tif, Format::Tiff, &map, &Palette::Fire, &[]).unwrap();
+    let mut decoder = tiff::decoder::Decoder::new(File::open(&tif).unwrap()).unwrap();
+    assert_eq!(decoder.dimensions().unwrap(), (3, 2));
+    match decoder.read_image().unwrap() {
+        tiff::decoder::DecodingResult::U8(pixels) => assert_eq!(pixels, map.paint(&Palette::Fire)),
+        _ => panic!("expected 8-bit samples"),
+    }
+
+    // 由逃逸值生成的格式不能直接写入着色后的像素
+    assert!(write_pixels(&raw, Format::Raw, &[0; 6], (3, 2), &Palette::Gray, &[]).is_err());
+    for file in [raw, huge, pgm, png16, tif] {
+        std::fs::remove_file(file).unwrap();
+    }
+}
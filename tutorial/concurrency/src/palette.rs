@@ -0,0 +1,214 @@
+use image::ColorType;
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+/// 调色板: 把逃逸时间映射成像素的颜色
+///
+/// Gray 保持原来的 8 位灰度输出, 其余的调色板都输出 RGB 三个通道
+#[derive(Debug, Clone, PartialEq)]
+pub enum Palette {
+    Gray,
+    Fire,
+    Ocean,
+    Hsv,
+    /// 用户提供的渐变, 每个色标是 (位置, [r, g, b]), 位置位于 [0, 1] 且按升序排列
+    Gradient(Vec<(f64, [u8; 3])>),
+}
+
+const FIRE: [(f64, [u8; 3]); 4] = [
+    (0.0, [0, 0, 0]),
+    (0.33, [200, 30, 0]),
+    (0.66, [255, 200, 0]),
+    (1.0, [255, 255, 255]),
+];
+
+const OCEAN: [(f64, [u8; 3]); 4] = [
+    (0.0, [0, 7, 100]),
+    (0.4, [32, 107, 203]),
+    (0.7, [237, 255, 255]),
+    (1.0, [255, 170, 0]),
+];
+
+impl Palette {
+    /// 根据名字选择内置调色板, 如果不是内置的名字就把它当作渐变文件的路径
+    pub fn from_name(name: &str) -> Result<Palette, Error> {
+        match name {
+            "gray" => Ok(Palette::Gray),
+            "fire" => Ok(Palette::Fire),
+            "ocean" => Ok(Palette::Ocean),
+            "hsv" => Ok(Palette::Hsv),
+            path => Palette::load_gradient(path),
+        }
+    }
+
+    /// 从文件中读取渐变
+    ///
+    /// 每行的格式为 `<position> <r> <g> <b>`, 空行和以 # 开头的行会被忽略
+    pub fn load_gradient(path: &str) -> Result<Palette, Error> {
+        let text = fs::read_to_string(path)?;
+        Palette::parse_gradient(&text)
+    }
+
+    fn parse_gradient(text: &str) -> Result<Palette, Error> {
+        let mut stops = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("line {}: expected `<position> <r> <g> <b>`, got {:?}", number + 1, line),
+                )
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 4 {
+                return Err(invalid());
+            }
+            let position: f64 = fields[0].parse().map_err(|_| invalid())?;
+            if !(0.0..=1.0).contains(&position) {
+                return Err(invalid());
+            }
+            let mut rgb = [0u8; 3];
+            for (channel, field) in rgb.iter_mut().zip(&fields[1..]) {
+                *channel = field.parse().map_err(|_| invalid())?;
+            }
+            stops.push((position, rgb));
+        }
+
+        if stops.len() < 2 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "a gradient needs at least two color stops",
+            ));
+        }
+        if stops.windows(2).any(|pair| pair[0].0 > pair[1].0) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "gradient positions must be in ascending order",
+            ));
+        }
+        Ok(Palette::Gradient(stops))
+    }
+
+    /// 每个像素占用的字节数
+    pub fn channels(&self) -> usize {
+        match self {
+            Palette::Gray => 1,
+            _ => 3,
+        }
+    }
+
+    /// 写 PNG 时使用的颜色类型
+    pub fn color_type(&self) -> ColorType {
+        match self {
+            Palette::Gray => ColorType::Gray(8),
+            _ => ColorType::RGB(8),
+        }
+    }
+
+    /// 把一个像素写入 pixel, pixel 的长度必须等于 channels()
+    ///
+    /// t 为 None 表示该点可能属于曼德博集, 总是画成黑色
+    /// 否则 t 是逃逸所需迭代次数与迭代上限的比值, 位于 [0, 1]
+    pub fn paint(&self, pixel: &mut [u8], t: Option<f64>) {
+        let t = match t {
+            None => {
+                pixel.iter_mut().for_each(|p| *p = 0);
+                return;
+            }
+            Some(t) => t.clamp(0.0, 1.0),
+        };
+        match self {
+            Palette::Gray => pixel[0] = 255 - (t * 255.0).round() as u8,
+            Palette::Fire => pixel.copy_from_slice(&interpolate(&FIRE, t)),
+            Palette::Ocean => pixel.copy_from_slice(&interpolate(&OCEAN, t)),
+            // 色相沿着色环循环 8 圈, 否则大部分像素都会挤在同一个颜色附近
+            Palette::Hsv => pixel.copy_from_slice(&hsv_to_rgb((t * 8.0).fract() * 360.0, 1.0, 1.0)),
+            Palette::Gradient(stops) => pixel.copy_from_slice(&interpolate(stops, t)),
+        }
+    }
+}
+
+/// 在相邻的两个色标之间做线性插值
+fn interpolate(stops: &[(f64, [u8; 3])], t: f64) -> [u8; 3] {
+    let (first, last) = (stops[0], stops[stops.len() - 1]);
+    if t <= first.0 {
+        return first.1;
+    }
+    if t >= last.0 {
+        return last.1;
+    }
+
+    let index = stops.iter().position(|&(position, _)| position > t).unwrap();
+    let ((p0, c0), (p1, c1)) = (stops[index - 1], stops[index]);
+    let ratio = (t - p0) / (p1 - p0);
+    let mut rgb = [0u8; 3];
+    for i in 0..3 {
+        rgb[i] = (c0[i] as f64 + (c1[i] as f64 - c0[i] as f64) * ratio).round() as u8;
+    }
+    rgb
+}
+
+#[test]
+fn test_interpolate() {
+    let stops = [(0.0, [0, 0, 0]), (0.5, [100, 200, 50]), (1.0, [200, 200, 250])];
+    assert_eq!(interpolate(&stops, 0.0), [0, 0, 0]);
+    assert_eq!(interpolate(&stops, 0.25), [50, 100, 25]);
+    assert_eq!(interpolate(&stops, 0.5), [100, 200, 50]);
+    assert_eq!(interpolate(&stops, 0.75), [150, 200, 150]);
+    assert_eq!(interpolate(&stops, 1.0), [200, 200, 250]);
+}
+
+/// 把 HSV 颜色 (h 的单位是度, s 和 v 位于 [0, 1]) 转换成 RGB
+fn hsv_to_rgb(h: f64, s: f64, v: f64) -> [u8; 3] {
+    let c = v * s;
+    let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+    let m = v - c;
+    let (r, g, b) = match (h / 60.0) as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    [
+        ((r + m) * 255.0).round() as u8,
+        ((g + m) * 255.0).round() as u8,
+        ((b + m) * 255.0).round() as u8,
+    ]
+}
+
+#[test]
+fn test_hsv_to_rgb() {
+    assert_eq!(hsv_to_rgb(0.0, 1.0, 1.0), [255, 0, 0]);
+    assert_eq!(hsv_to_rgb(120.0, 1.0, 1.0), [0, 255, 0]);
+    assert_eq!(hsv_to_rgb(240.0, 1.0, 1.0), [0, 0, 255]);
+    assert_eq!(hsv_to_rgb(60.0, 1.0, 0.5), [128, 128, 0]);
+}
+
+#[test]
+fn test_parse_gradient() {
+    assert_eq!(
+        Palette::parse_gradient("# comment\n0.0 0 0 0\n\n1.0 255 128 0\n").unwrap(),
+        Palette::Gradient(vec![(0.0, [0, 0, 0]), (1.0, [255, 128, 0])])
+    );
+    assert!(Palette::parse_gradient("0.0 0 0 0\n").is_err());
+    assert!(Palette::parse_gradient("0.0 0 0 0\n1.0 256 0 0\n").is_err());
+    assert!(Palette::parse_gradient("0.5 0 0 0\n0.2 1 1 1\n").is_err());
+    assert!(Palette::parse_gradient("0.0 0 0\n1.0 1 1 1\n").is_err());
+}
+
+#[test]
+fn test_paint_gray() {
+    // 灰度输出必须和原来的 255 - count 完全一致
+    let mut pixel = [0u8];
+    for count in 0..255usize {
+        Palette::Gray.paint(&mut pixel, Some(count as f64 / 255.0));
+        assert_eq!(pixel[0], 255 - count as u8);
+    }
+    Palette::Gray.paint(&mut pixel, None);
+    assert_eq!(pixel[0], 0);
+}
@@ -0,0 +1,205 @@
+use crate::{equalize, render_region, RenderError, RenderOptions};
+use num::Complex;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// 渲染整幅图像时使用的并发后端
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    /// 在当前线程中直接调用 render
+    Single,
+    /// crossbeam::scope 加上动态的行调度, 见 render_parallel
+    Crossbeam,
+    /// rayon 的 par_chunks_mut, 见 render_rayon
+    Rayon,
+}
+
+impl Backend {
+    pub const ALL: [Backend; 3] = [Backend::Single, Backend::Crossbeam, Backend::Rayon];
+
+    pub fn from_name(name: &str) -> Option<Backend> {
+        Backend::ALL.iter().copied().find(|backend| backend.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Single => "single",
+            Backend::Crossbeam => "crossbeam",
+            Backend::Rayon => "rayon",
+        }
+    }
+
+    /// 用这个后端渲染整幅图像, Single 会忽略 threads
+    ///
+    /// options.equalize 为 true 时改用两遍的均衡化渲染, 除了 Single 只用一个线程以外, 各个后端的结果相同
+    /// 只有 Rayon 会返回错误: 建立线程池时无法启动线程, 返回 RenderError::ThreadPool
+    pub fn render(
+        &self,
+        pixels: &mut [u8],
+        bounds: (usize, usize),
+        upper_left: Complex<f64>,
+        lower_right: Complex<f64>,
+        options: &RenderOptions,
+        threads: usize,
+    ) -> Result<(), RenderError> {
+        // 均衡化需要整幅图像的直方图, 两遍都由 equalize 模块完成
+        if options.equalize {
+            let threads = if *self == Backend::Single { 1 } else { threads };
+            equalize::render_equalized(pixels, bounds, upper_left, lower_right, options, threads);
+            return Ok(());
+        }
+        self.render_region(pixels, bounds, ((0, 0), bounds), (upper_left, lower_right), options, threads)
+    }
+
+    /// 用这个后端渲染整幅图像中的一个区域, 参数的含义与 crate::render_region 相同, corners 是整幅图像的两个角点
+    ///
+    /// 区域可以是检查点中的一块, 不做均衡化
+    pub fn render_region(
+        &self,
+        pixels: &mut [u8],
+        bounds: (usize, usize),
+        region: ((usize, usize), (usize, usize)),
+        corners: (Complex<f64>, Complex<f64>),
+        options: &RenderOptions,
+        threads: usize,
+    ) -> Result<(), RenderError> {
+        let (upper_left, lower_right) = corners;
+        match self {
+            Backend::Single => render_region(pixels, bounds, region, upper_left, lower_right, options),
+            Backend::Crossbeam => render_parallel(pixels, bounds, region, upper_left, lower_right, options, threads),
+            Backend::Rayon => return render_rayon(pixels, bounds, region, upper_left, lower_right, options, threads),
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_backend_from_name() {
+    for backend in Backend::ALL {
+        assert_eq!(Backend::from_name(backend.name()), Some(backend));
+    }
+    assert_eq!(Backend::from_name("tokio"), None);
+}
+
+/// 默认的线程数: 机器可用的并行度, 取不到时退化为单线程
+pub fn default_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// 用 threads 个线程并发渲染整幅图像中的一个区域, 参数的含义与 render_region 相同
+///
+/// 每一行都交给 render_region 按整幅图像的几何关系渲染, 所以结果与单线程渲染逐位一致
+pub fn render_parallel(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    region: ((usize, usize), (usize, usize)),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    options: &RenderOptions,
+    threads: usize,
+) {
+    let ((left, top), size) = region;
+    let row_len = size.0 * options.palette.channels();
+    assert!(pixels.len() == row_len * size.1);
+
+    render_rows_parallel(pixels, row_len, threads, |row, band| {
+        render_region(band, bounds, ((left, top + row), (size.0, 1)), upper_left, lower_right, options);
+    });
+}
+
+/// 用 threads 个线程并发地对缓冲区中的每一行调用 render_row(row, band), band 是第 row 行的像素
+///
+/// 固定划分条带时, 穿过集合内部的条带要比其他条带慢得多, 先完成的线程只能闲等
+/// 这里改为动态调度: 所有线程共享同一个行迭代器, 每个线程渲染完一行后再去领取下一行, 直到所有的行都被领完
+pub fn render_rows_parallel<T, F>(pixels: &mut [T], row_len: usize, threads: usize, render_row: F)
+where
+    T: Send,
+    F: Fn(usize, &mut [T]) + Sync,
+{
+    assert!(threads > 0);
+
+    // chunks_mut 把缓冲区切成互不重叠的行, 每一行只会被一个线程领取, 所以可以安全地并发写入
+    // Mutex 保护的只是迭代器本身, 领取一行只需要很短的时间, 真正的渲染在锁外进行
+    let rows = Mutex::new(pixels.chunks_mut(row_len).enumerate());
+    crossbeam::scope(|spawner| {
+        for _ in 0..threads {
+            spawner.spawn(|_| loop {
+                // 锁在这条语句结束时就会释放
+                let next = rows.lock().unwrap().next();
+                let (row, band) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+                render_row(row, band);
+            });
+        }
+    })
+    .unwrap();
+}
+
+/// threads 个线程的 rayon 线程池; 每种线程数只建立一次, 之后的渲染 (例如动画的每一帧) 都复用它
+///
+/// 无法启动线程时返回错误, 下一次调用会重新尝试
+fn rayon_pool(threads: usize) -> Result<Arc<rayon::ThreadPool>, RenderError> {
+    static POOLS: Mutex<BTreeMap<usize, Arc<rayon::ThreadPool>>> = Mutex::new(BTreeMap::new());
+    let mut pools = POOLS.lock().unwrap();
+    if let Some(pool) = pools.get(&threads) {
+        return Ok(Arc::clone(pool));
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|source| RenderError::ThreadPool { threads, source })?;
+    let pool = Arc::new(pool);
+    pools.insert(threads, Arc::clone(&pool));
+    Ok(pool)
+}
+
+/// 用 rayon 并发渲染整幅图像中的一个区域, 参数的含义与 render_parallel 相同
+///
+/// par_chunks_mut 同样按行切分缓冲区, rayon 的线程池会通过工作窃取 (work stealing) 自动平衡各个线程的负载
+/// 线程池见 rayon_pool, 建立失败时返回错误
+pub fn render_rayon(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    region: ((usize, usize), (usize, usize)),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    options: &RenderOptions,
+    threads: usize,
+) -> Result<(), RenderError> {
+    let ((left, top), size) = region;
+    let row_len = size.0 * options.palette.channels();
+    assert!(threads > 0);
+    assert!(pixels.len() == row_len * size.1);
+
+    // install 会在线程池中执行闭包
+    rayon_pool(threads)?.install(|| {
+        pixels.par_chunks_mut(row_len).enumerate().for_each(|(row, band)| {
+            render_region(band, bounds, ((left, top + row), (size.0, 1)), upper_left, lower_right, options);
+        });
+    });
+    Ok(())
+}
+
+#[test]
+fn test_render_parallel() {
+    // 并发渲染的结果必须和单线程渲染完全一致
+    let bounds = (64, 48);
+    let upper_left = Complex { re: -2.0, im: 1.2 };
+    let lower_right = Complex { re: 1.0, im: -1.2 };
+    let options = RenderOptions::default();
+
+    let mut expected = vec![0; bounds.0 * bounds.1];
+    crate::render(&mut expected, bounds, upper_left, lower_right, &options);
+    for threads in [1, 3, 8] {
+        for backend in Backend::ALL {
+            let mut pixels = vec![0; bounds.0 * bounds.1];
+            backend.render(&mut pixels, bounds, upper_left, lower_right, &options, threads).unwrap();
+            assert_eq!(pixels, expected, "{} with {} threads", backend.name(), threads);
+        }
+    }
+    // 同一个线程数的线程池只建立一次
+    assert!(Arc::ptr_eq(&rayon_pool(3).unwrap(), &rayon_pool(3).unwrap()));
+}
@@ -0,0 +1,140 @@
+//! 渲染进度和协作式取消
+//!
+//! 所有渲染线程共享同一个 Progress: 每渲染完一行就把原子计数器加一, 开始新的一行之前先检查是否已经取消
+//! 另有一个报告线程定期把完成的百分比和预计剩余时间写到标准错误输出
+//! 按下 Ctrl-C 时只是设置取消标志, 渲染线程各自在行与行之间停下来, 已经渲染的部分仍然可以写入文件
+
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub struct Progress {
+    /// 总的工作量, 通常是图像的行数
+    total: usize,
+    done: AtomicUsize,
+    /// 不需要渲染就已经完成的工作量, 例如恢复时已有的块, 估计速度时不计入
+    skipped: AtomicUsize,
+    cancelled: AtomicBool,
+    finished: AtomicBool,
+    start: Instant,
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Progress {
+        Progress {
+            total,
+            done: AtomicUsize::new(0),
+            skipped: AtomicUsize::new(0),
+            cancelled: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+            start: Instant::now(),
+        }
+    }
+
+    /// 记录完成了 units 个单位的工作
+    pub fn advance(&self, units: usize) {
+        // 计数器只用来显示, 不需要与其他内存操作同步
+        self.done.fetch_add(units, Ordering::Relaxed);
+    }
+
+    /// 记录有 units 个单位的工作已经提前完成, 不需要渲染
+    pub fn skip(&self, units: usize) {
+        self.skipped.fetch_add(units, Ordering::Relaxed);
+        self.done.fetch_add(units, Ordering::Relaxed);
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// 已完成的比例, 在 [0, 1] 之间
+    pub fn fraction(&self) -> f64 {
+        if self.total == 0 {
+            return 1.0;
+        }
+        (self.done.load(Ordering::Relaxed) as f64 / self.total as f64).min(1.0)
+    }
+
+    /// 按这次运行以来的平均速度估计的剩余时间, 还没有渲染完任何工作时返回 None
+    pub fn eta(&self) -> Option<Duration> {
+        let done = self.done.load(Ordering::Relaxed).min(self.total);
+        let rendered = done.saturating_sub(self.skipped.load(Ordering::Relaxed));
+        if rendered == 0 {
+            return None;
+        }
+        Some(self.start.elapsed().mul_f64((self.total - done) as f64 / rendered as f64))
+    }
+
+    /// 例如 " 42.5% ETA 13s"
+    pub fn report(&self) -> String {
+        let eta = match self.eta() {
+            None => "?".to_string(),
+            Some(eta) => format!("{}s", eta.as_secs()),
+        };
+        format!("{:5.1}% ETA {}", self.fraction() * 100.0, eta)
+    }
+
+    /// 启动报告线程, 每隔 interval 在标准错误输出的同一行上刷新一次进度, 直到调用 finish
+    pub fn spawn_reporter(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let progress = Arc::clone(self);
+        thread::spawn(move || {
+            while !progress.finished.load(Ordering::Relaxed) {
+                eprint!("\r{}", progress.report());
+                let _ = std::io::stderr().flush();
+                thread::park_timeout(interval);
+            }
+            eprintln!("\r{}", progress.report());
+        })
+    }
+
+    /// 通知报告线程输出最后一次进度后退出, 并等待它结束
+    pub fn finish(&self, reporter: JoinHandle<()>) {
+        self.finished.store(true, Ordering::Relaxed);
+        reporter.thread().unpark();
+        reporter.join().unwrap();
+    }
+}
+
+#[test]
+fn test_progress() {
+    let progress = Progress::new(4);
+    assert_eq!(progress.eta(), None);
+    assert!(progress.report().starts_with("  0.0% ETA ?"));
+    crossbeam::scope(|spawner| {
+        for _ in 0..3 {
+            spawner.spawn(|_| progress.advance(1));
+        }
+    })
+    .unwrap();
+    assert_eq!(progress.fraction(), 0.75);
+    assert!(progress.report().starts_with(" 75.0% ETA "));
+    assert!(!progress.is_cancelled());
+    progress.cancel();
+    assert!(progress.is_cancelled());
+
+    // 跳过的工作计入完成的比例, 但不参与估计剩余时间
+    let resumed = Progress::new(10);
+    resumed.skip(5);
+    assert_eq!(resumed.fraction(), 0.5);
+    assert_eq!(resumed.eta(), None);
+}
+
+/// 按下 Ctrl-C 时取消 progress 对应的渲染; 第二次按下时不再等待, 直接退出
+///
+/// 一个进程只能安装一个处理函数, 已经安装过时返回 ctrlc::Error::MultipleHandlers
+#[cfg(feature = "ctrlc")]
+pub fn cancel_on_ctrlc(progress: &Arc<Progress>) -> Result<(), ctrlc::Error> {
+    let progress = Arc::clone(progress);
+    ctrlc::set_handler(move || {
+        if progress.is_cancelled() {
+            std::process::exit(130);
+        }
+        progress.cancel();
+    })
+}
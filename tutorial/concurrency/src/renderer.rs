@@ -0,0 +1,148 @@
+//! 供其他程序嵌入的渲染接口
+//!
+//! Renderer 把渲染参数和并发方式放在一起, 之后可以反复渲染不同的视口
+//! 与命令行程序一样, 渲染之前先检查渲染参数, 图像尺寸和视口, 不合法时返回 RenderError 而不是 panic
+
+use crate::output::{self, EscapeMap, Format};
+use crate::parallel::{self, Backend};
+use crate::viewport::{self, Viewport};
+use crate::{render_escapes, RenderError, RenderOptions};
+use num::Complex;
+use std::io;
+
+#[derive(Clone)]
+pub struct Renderer {
+    pub options: RenderOptions,
+    /// 渲染整幅图像时使用的并发后端
+    pub backend: Backend,
+    /// 并发渲染的线程数, Backend::Single 会忽略它
+    pub threads: usize,
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Renderer::new(RenderOptions::default())
+    }
+}
+
+impl Renderer {
+    /// 使用 crossbeam 后端, 线程数为机器可用的并行度
+    pub fn new(options: RenderOptions) -> Renderer {
+        Renderer { options, backend: Backend::Crossbeam, threads: parallel::default_threads() }
+    }
+
+    /// 检查渲染参数和线程数, 见 RenderOptions::check; 每次渲染之前都会先检查
+    pub fn check(&self) -> Result<(), RenderError> {
+        if self.threads == 0 {
+            let reason = "must be positive".to_string();
+            return Err(RenderError::Options { option: "threads", value: self.threads.to_string(), reason });
+        }
+        self.options.check()
+    }
+
+    /// 渲染 viewport 中 bounds 大小的图像, 返回按行排列的像素, 每个像素占用 options.palette.channels() 个字节
+    ///
+    /// 视口的旋转取代 options.rotation
+    pub fn render(&self, bounds: (usize, usize), viewport: &Viewport) -> Result<Vec<u8>, RenderError> {
+        self.check()?;
+        viewport::check_bounds(bounds)?;
+        let (upper_left, lower_right) = viewport.check(bounds)?;
+        let options = RenderOptions { rotation: viewport.rotation(), ..self.options.clone() };
+        self.render_with(bounds, upper_left, lower_right, &options)
+    }
+
+    /// 渲染两个角点围成的矩形, 旋转由 options.rotation 决定
+    pub fn render_corners(
+        &self,
+        bounds: (usize, usize),
+        upper_left: Complex<f64>,
+        lower_right: Complex<f64>,
+    ) -> Result<Vec<u8>, RenderError> {
+        self.render_with(bounds, upper_left, lower_right, &self.options)
+    }
+
+    fn render_with(
+        &self,
+        bounds: (usize, usize),
+        upper_left: Complex<f64>,
+        lower_right: Complex<f64>,
+        options: &RenderOptions,
+    ) -> Result<Vec<u8>, RenderError> {
+        self.check()?;
+        viewport::check_bounds(bounds)?;
+        options.check_row(bounds.0)?;
+        viewport::check_corners(bounds, upper_left, lower_right)?;
+        let mut pixels = vec![0; bounds.0 * bounds.1 * options.palette.channels()];
+        self.backend.render(&mut pixels, bounds, upper_left, lower_right, options, self.threads)?;
+        Ok(pixels)
+    }
+
+    /// 只计算每个像素的逃逸值而不着色, 之后可以用 EscapeMap::paint 换不同的调色板着色; 不做超采样
+    pub fn escapes(&self, bounds: (usize, usize), viewport: &Viewport) -> Result<EscapeMap, RenderError> {
+        self.check()?;
+        viewport::check_bounds(bounds)?;
+        let (upper_left, lower_right) = viewport.check(bounds)?;
+        let options = RenderOptions { rotation: viewport.rotation(), ..self.options.clone() };
+        Ok(render_escapes(bounds, upper_left, lower_right, &options, self.threads))
+    }
+
+    /// 渲染 viewport 并写入 filename, 格式由扩展名决定, 规则与 Format::from_filename 相同
+    pub fn render_to_file(&self, filename: &str, bounds: (usize, usize), viewport: &Viewport) -> Result<(), RenderError> {
+        let format = Format::from_filename(filename).ok_or_else(|| {
+            RenderError::io(filename)(io::Error::new(io::ErrorKind::InvalidInput, "unknown image format"))
+        })?;
+        let palette = &self.options.palette;
+        if format.stores_escapes() {
+            let map = self.escapes(bounds, viewport)?;
+            return output::write_escapes(filename, format, &map, palette, &[]).map_err(RenderError::io(filename));
+        }
+        let pixels = self.render(bounds, viewport)?;
+        output::write_pixels(filename, format, &pixels, bounds, palette, &[]).map_err(RenderError::io(filename))
+    }
+}
+
+#[test]
+fn test_renderer() {
+    let renderer = Renderer { threads: 3, ..Renderer::default() };
+    let viewport = Viewport { center: Complex { re: -0.5, im: 0.0 }, zoom: 1.0, rotation: 30.0 };
+    let bounds = (40, 30);
+
+    // 与直接调用 render 的结果相同
+    let (upper_left, lower_right) = viewport.corners(bounds);
+    let options = RenderOptions { rotation: viewport.rotation(), ..RenderOptions::default() };
+    let mut expected = vec![0; bounds.0 * bounds.1];
+    crate::render(&mut expected, bounds, upper_left, lower_right, &options);
+    assert_eq!(renderer.render(bounds, &viewport).unwrap(), expected);
+    assert_eq!(renderer.escapes(bounds, &viewport).unwrap().paint(&renderer.options.palette), expected);
+
+    assert!(matches!(renderer.render((0, 30), &viewport), Err(RenderError::Dimension { .. })));
+    let tiny = Viewport { zoom: 1e20, ..viewport };
+    assert!(matches!(renderer.render(bounds, &tiny), Err(RenderError::Viewport { .. })));
+    assert!(matches!(renderer.render_to_file("mandel.gif", bounds, &viewport), Err(RenderError::Io { .. })));
+
+    // 不合法的参数返回错误而不是在渲染线程中 panic
+    let option = |renderer: Renderer| match renderer.render(bounds, &viewport) {
+        Err(RenderError::Options { option, .. }) => option,
+        other => panic!("{:?}", other.map(|pixels| pixels.len())),
+    };
+    let with = |options: RenderOptions| Renderer { options, ..renderer.clone() };
+    assert_eq!(option(Renderer { threads: 0, ..renderer.clone() }), "threads");
+    assert_eq!(option(with(RenderOptions { samples: 0, ..RenderOptions::default() })), "samples");
+    assert_eq!(option(with(RenderOptions { samples: crate::MAX_SAMPLES + 1, ..RenderOptions::default() })), "samples");
+    assert_eq!(option(with(RenderOptions { samples: 5_000_000_000, ..RenderOptions::default() })), "samples");
+    assert_eq!(option(with(RenderOptions { limit: 0, ..RenderOptions::default() })), "limit");
+    assert_eq!(option(with(RenderOptions { escape_radius: 1.5, ..RenderOptions::default() })), "escape_radius");
+    assert_eq!(option(with(RenderOptions { escape_radius: f64::NAN, ..RenderOptions::default() })), "escape_radius");
+    assert_eq!(option(with(RenderOptions { escape_radius: f64::INFINITY, ..RenderOptions::default() })), "escape_radius");
+    assert_eq!(option(with(RenderOptions { equalize: true, samples: 2, ..RenderOptions::default() })), "equalize");
+    assert_eq!(option(with(RenderOptions { equalize: true, jitter: true, ..RenderOptions::default() })), "equalize");
+    let escapes = Renderer { threads: 0, ..renderer.clone() }.escapes(bounds, &viewport);
+    assert!(matches!(escapes, Err(RenderError::Options { option: "threads", .. })));
+
+    // 旋转角度不是有限的数时整幅图像都会是 NaN 坐标
+    for rotation in [f64::NAN, f64::INFINITY] {
+        let spinning = Viewport { rotation, ..viewport };
+        assert!(matches!(renderer.render(bounds, &spinning), Err(RenderError::Options { option: "rotation", .. })));
+        assert!(matches!(renderer.escapes(bounds, &spinning), Err(RenderError::Options { option: "rotation", .. })));
+    }
+}
@@ -0,0 +1,239 @@
+//! 瓦片服务器: 通过 HTTP 提供 /tiles/{z}/{x}/{y}.png, 配合一个简单的网页就可以在浏览器中拖动和缩放
+//! 网页自己在 canvas 上拼接瓦片, 不依赖任何外部的脚本, 离线时也能使用
+//!
+//! 瓦片的编号与网络地图相同: 第 z 级把整个视野分成 2^z x 2^z 块, x 向右增加, y 向下增加
+//! 每块瓦片在 actix-web 的阻塞线程池中单线程渲染, 同时请求的多块瓦片由不同的线程并发渲染
+//! 编码好的 PNG 保存在 LRU 缓存中, 来回拖动时不必重新渲染
+
+use crate::{encode_png, pixel_to_point, render_region, RenderError, RenderOptions};
+use actix_web::{web, App, HttpResponse, HttpServer};
+use lru::LruCache;
+use num::Complex;
+use serde::Deserialize;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+/// 瓦片的边长, 单位为像素
+pub const TILE_SIZE: usize = 256;
+
+/// 最大的缩放级别, 再放大下去 f64 的精度就不够了
+pub const MAX_ZOOM: u32 = 40;
+
+/// 第 0 级唯一的一块瓦片覆盖的正方形
+const WORLD_UPPER_LEFT: Complex<f64> = Complex { re: -2.5, im: 2.0 };
+const WORLD_LOWER_RIGHT: Complex<f64> = Complex { re: 1.5, im: -2.0 };
+
+/// 第 z 级第 y 行第 x 列的瓦片在复平面上的左上角和右下角, 编号超出范围时返回 None
+///
+/// 把第 z 级的整个视野看作一幅 TILE_SIZE * 2^z 像素见方的图像, 瓦片的角点就是其中对应像素的 pixel_to_point,
+/// 所以相邻的瓦片严丝合缝
+pub fn tile_bounds(z: u32, x: u64, y: u64) -> Option<(Complex<f64>, Complex<f64>)> {
+    if z > MAX_ZOOM || x >> z != 0 || y >> z != 0 {
+        return None;
+    }
+    let side = TILE_SIZE << z;
+    let (column, row) = (x as usize * TILE_SIZE, y as usize * TILE_SIZE);
+    Some((
+        pixel_to_point((side, side), (column, row), WORLD_UPPER_LEFT, WORLD_LOWER_RIGHT),
+        pixel_to_point((side, side), (column + TILE_SIZE, row + TILE_SIZE), WORLD_UPPER_LEFT, WORLD_LOWER_RIGHT),
+    ))
+}
+
+#[test]
+fn test_tile_bounds() {
+    assert_eq!(tile_bounds(0, 0, 0), Some((WORLD_UPPER_LEFT, WORLD_LOWER_RIGHT)));
+    assert_eq!(
+        tile_bounds(1, 1, 0),
+        Some((Complex { re: -0.5, im: 2.0 }, Complex { re: 1.5, im: 0.0 }))
+    );
+    assert_eq!(
+        tile_bounds(2, 0, 3),
+        Some((Complex { re: -2.5, im: -1.0 }, Complex { re: -1.5, im: -2.0 }))
+    );
+    assert_eq!(tile_bounds(1, 2, 0), None);
+    assert_eq!(tile_bounds(MAX_ZOOM + 1, 0, 0), None);
+}
+
+/// 所有请求共享的状态: 渲染参数和缓存
+pub struct TileServer {
+    options: RenderOptions,
+    /// 以 (z, x, y) 为键的 PNG 数据
+    cache: Mutex<LruCache<(u32, u64, u64), web::Bytes>>,
+}
+
+impl TileServer {
+    /// cache_tiles 是缓存中最多保存的瓦片数, 必须是正数; 渲染参数按 RenderOptions::check 检查
+    pub fn new(options: RenderOptions, cache_tiles: usize) -> Result<TileServer, RenderError> {
+        options.check()?;
+        let capacity = NonZeroUsize::new(cache_tiles).ok_or_else(|| RenderError::Options {
+            option: "cache_tiles",
+            value: cache_tiles.to_string(),
+            reason: "must be positive".to_string(),
+        })?;
+        Ok(TileServer { options, cache: Mutex::new(LruCache::new(capacity)) })
+    }
+
+    /// 渲染第 z 级第 y 行第 x 列的瓦片并编码成 PNG, 编号必须在 tile_bounds 认可的范围内
+    ///
+    /// 瓦片作为第 z 级整个视野的一个区域渲染, 所以超采样时相邻的瓦片之间也没有接缝
+    fn render_tile(&self, z: u32, x: u64, y: u64) -> Vec<u8> {
+        let side = TILE_SIZE << z;
+        let origin = (x as usize * TILE_SIZE, y as usize * TILE_SIZE);
+        let mut pixels = vec![0; TILE_SIZE * TILE_SIZE * self.options.palette.channels()];
+        let region = (origin, (TILE_SIZE, TILE_SIZE));
+        render_region(&mut pixels, (side, side), region, WORLD_UPPER_LEFT, WORLD_LOWER_RIGHT, &self.options);
+        let mut png = Vec::new();
+        let bounds = (TILE_SIZE, TILE_SIZE);
+        encode_png(&mut png, &pixels, bounds, self.options.palette.color_type(), &[]).expect("error encoding tile");
+        png
+    }
+}
+
+#[derive(Deserialize)]
+struct TilePath {
+    z: u32,
+    x: u64,
+    y: u64,
+}
+
+async fn get_tile(server: web::Data<TileServer>, path: web::Path<TilePath>) -> actix_web::Result<HttpResponse> {
+    let TilePath { z, x, y } = path.into_inner();
+    if tile_bounds(z, x, y).is_none() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    // 渲染期间不持有锁; 两个请求同时要同一块瓦片时各自渲染一次, 结果相同
+    let cached = server.cache.lock().unwrap().get(&(z, x, y)).cloned();
+    let png = match cached {
+        Some(png) => png,
+        None => {
+            let renderer = server.clone();
+            let png = web::Bytes::from(web::block(move || renderer.render_tile(z, x, y)).await?);
+            server.cache.lock().unwrap().put((z, x, y), png.clone());
+            png
+        }
+    };
+    Ok(HttpResponse::Ok().content_type("image/png").body(png))
+}
+
+/// 浏览页面: 在 canvas 上画出视野内的瓦片, 拖动平移, 滚轮按整数级缩放
+async fn get_index() -> HttpResponse {
+    HttpResponse::Ok().content_type("text/html").body(format!(
+        r#"<!DOCTYPE html>
+<title> Mandelbrot </title>
+<style> html, body {{ margin: 0; height: 100%; overflow: hidden; background: #000; }} canvas {{ display: block; cursor: grab; }} </style>
+<canvas id="map"></canvas>
+<script>
+    const size = {size}, maxZoom = {max_zoom};
+    const canvas = document.getElementById("map"), context = canvas.getContext("2d");
+    // 当前级别 z 的瓦片; (originX, originY) 是窗口左上角在第 z 级整个视野中的像素坐标
+    let tiles = new Map(), z = 0, originX = 0, originY = 0;
+
+    function tile(x, y) {{
+        const key = `${{z}}/${{x}}/${{y}}`;
+        if (!tiles.has(key)) {{
+            const image = new Image();
+            image.onload = draw;
+            image.src = `/tiles/${{key}}.png`;
+            tiles.set(key, image);
+        }}
+        return tiles.get(key);
+    }}
+
+    function draw() {{
+        context.fillRect(0, 0, canvas.width, canvas.height);
+        const count = 2 ** z;
+        const columns = [Math.max(0, Math.floor(originX / size)), Math.min(count, Math.ceil((originX + canvas.width) / size))];
+        const rows = [Math.max(0, Math.floor(originY / size)), Math.min(count, Math.ceil((originY + canvas.height) / size))];
+        for (let y = rows[0]; y < rows[1]; y++) {{
+            for (let x = columns[0]; x < columns[1]; x++) {{
+                const image = tile(x, y);
+                if (image.complete && image.naturalWidth) {{
+                    context.drawImage(image, x * size - originX, y * size - originY);
+                }}
+            }}
+        }}
+    }}
+
+    function resize() {{
+        canvas.width = innerWidth;
+        canvas.height = innerHeight;
+        draw();
+    }}
+
+    canvas.addEventListener("pointerdown", event => canvas.setPointerCapture(event.pointerId));
+    canvas.addEventListener("pointermove", event => {{
+        if (canvas.hasPointerCapture(event.pointerId)) {{
+            originX -= event.movementX;
+            originY -= event.movementY;
+            draw();
+        }}
+    }});
+    // 缩放时保持鼠标下的点不动
+    canvas.addEventListener("wheel", event => {{
+        event.preventDefault();
+        const step = event.deltaY < 0 ? 1 : -1;
+        if (z + step < 0 || z + step > maxZoom) return;
+        originX = (originX + event.offsetX) * 2 ** step - event.offsetX;
+        originY = (originY + event.offsetY) * 2 ** step - event.offsetY;
+        z += step;
+        tiles = new Map();
+        draw();
+    }}, {{ passive: false }});
+    addEventListener("resize", resize);
+
+    // 开始时第 0 级唯一的瓦片位于窗口中央
+    originX = (size - innerWidth) / 2;
+    originY = (size - innerHeight) / 2;
+    resize();
+</script>
+"#,
+        size = TILE_SIZE,
+        max_zoom = MAX_ZOOM,
+    ))
+}
+
+/// 注册浏览页面和瓦片的路由, 需要通过 app_data 提供 web::Data<TileServer>
+pub fn routes(config: &mut web::ServiceConfig) {
+    config
+        .route("/", web::get().to(get_index))
+        .route("/tiles/{z}/{x}/{y}.png", web::get().to(get_tile));
+}
+
+#[actix_web::test]
+async fn test_get_tile() {
+    use actix_web::test;
+
+    assert!(matches!(
+        TileServer::new(RenderOptions::default(), 0),
+        Err(RenderError::Options { option: "cache_tiles", .. })
+    ));
+    let server = web::Data::new(TileServer::new(RenderOptions { limit: 50, ..RenderOptions::default() }, 2).unwrap());
+    let app = test::init_service(App::new().app_data(server.clone()).configure(routes)).await;
+
+    let response = test::call_service(&app, test::TestRequest::get().uri("/tiles/1/0/1.png").to_request()).await;
+    assert!(response.status().is_success());
+    assert_eq!(test::read_body(response).await, server.render_tile(1, 0, 1));
+    assert!(server.cache.lock().unwrap().contains(&(1, 0, 1)));
+
+    for uri in ["/tiles/1/2/0.png", "/tiles/1/0/x.png"] {
+        let response = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(response.status(), 404, "{}", uri);
+    }
+    let response = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+    assert!(response.status().is_success());
+    // 页面不引用其他站点的资源
+    let page = test::read_body(response).await;
+    assert!(!String::from_utf8_lossy(&page).contains("://"));
+}
+
+/// 在 address 上启动瓦片服务器, workers 是处理请求的线程数, 直到进程被终止才返回
+pub fn serve(address: &str, server: TileServer, workers: usize) -> std::io::Result<()> {
+    let server = web::Data::new(server);
+    actix_web::rt::System::new().block_on(async move {
+        HttpServer::new(move || App::new().app_data(server.clone()).configure(routes))
+            .workers(workers)
+            .bind(address)?
+            .run()
+            .await
+    })
+}
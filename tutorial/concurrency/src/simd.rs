@@ -0,0 +1,119 @@
+use crate::{smooth_escape, RenderOptions};
+use num::Complex;
+use std::ops::{Add, Mul, Sub};
+
+/// 每组同时迭代的点数
+pub const LANES: usize = 4;
+
+/// 4 个 f64 组成的向量
+///
+/// 对它的逐元素运算写成固定长度数组上的循环, 编译器会把它们编译成 SIMD 指令 (例如 SSE2 或 AVX)
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct F64x4([f64; LANES]);
+
+impl F64x4 {
+    fn splat(value: f64) -> F64x4 {
+        F64x4([value; LANES])
+    }
+}
+
+impl Add for F64x4 {
+    type Output = F64x4;
+    fn add(self, rhs: F64x4) -> F64x4 {
+        F64x4(std::array::from_fn(|lane| self.0[lane] + rhs.0[lane]))
+    }
+}
+
+impl Sub for F64x4 {
+    type Output = F64x4;
+    fn sub(self, rhs: F64x4) -> F64x4 {
+        F64x4(std::array::from_fn(|lane| self.0[lane] - rhs.0[lane]))
+    }
+}
+
+impl Mul for F64x4 {
+    type Output = F64x4;
+    fn mul(self, rhs: F64x4) -> F64x4 {
+        F64x4(std::array::from_fn(|lane| self.0[lane] * rhs.0[lane]))
+    }
+}
+
+/// 同时对 LANES 个点迭代 z = z * z + c, 是 escape_time 的向量化版本
+///
+/// 实部和虚部分别存放在两个向量中, 每个通道 (lane) 用一个掩码记录自己是否已经逃逸
+/// 已经逃逸的通道会继续参与运算, 但结果不再被记录; 所有通道都逃逸后提前结束
+/// 每个通道返回 Some((i, z)), 其中 i 与 escape_time 的返回值相同, z 是逃逸时的值; 没有逃逸则返回 None
+/// 运算顺序与 Complex 的乘法和加法完全相同, 因此结果与逐点调用 escape_time 逐位一致
+pub fn escape_time_x4(
+    z: [Complex<f64>; LANES],
+    c: [Complex<f64>; LANES],
+    limit: usize,
+    escape_radius: f64,
+) -> [Option<(usize, Complex<f64>)>; LANES] {
+    let bailout = F64x4::splat(escape_radius * escape_radius);
+    let (mut zr, mut zi) = (F64x4(z.map(|z| z.re)), F64x4(z.map(|z| z.im)));
+    let (cr, ci) = (F64x4(c.map(|c| c.re)), F64x4(c.map(|c| c.im)));
+    let mut result = [None; LANES];
+    let mut active = [true; LANES];
+
+    for i in 0..limit {
+        let (zr2, zi2) = (zr * zr, zi * zi);
+        let norm_sqr = zr2 + zi2;
+        for lane in 0..LANES {
+            if active[lane] && norm_sqr.0[lane] > bailout.0[lane] {
+                active[lane] = false;
+                result[lane] = Some((i, Complex { re: zr.0[lane], im: zi.0[lane] }));
+            }
+        }
+        if !active.contains(&true) {
+            break;
+        }
+
+        let zrzi = zr * zi;
+        zr = zr2 - zi2 + cr;
+        zi = zrzi + zrzi + ci;
+    }
+    result
+}
+
+/// 计算 LANES 个点的逃逸值, 含义与 render 中逐点计算的逃逸值相同
+///
+/// 只适用于 z = z^2 + c 的公式, 调用者需要先检查 options.fractal.is_quadratic()
+pub fn escape_values(points: [Complex<f64>; LANES], options: &RenderOptions) -> [Option<f64>; LANES] {
+    let (z, c) = match options.julia {
+        None => ([Complex { re: 0.0, im: 0.0 }; LANES], points),
+        Some(c) => (points, [c; LANES]),
+    };
+    let bailout = options.escape_radius * options.escape_radius;
+    escape_time_x4(z, c, options.limit, options.escape_radius).map(|escape| {
+        escape.map(|(i, z)| {
+            if options.smooth {
+                smooth_escape(i, z, bailout, 2.0, options.limit)
+            } else {
+                i as f64
+            }
+        })
+    })
+}
+
+#[test]
+fn test_escape_time_x4() {
+    // 在一个覆盖集合内外的网格上, 向量化内核必须与 escape_time 逐点一致
+    let zero = Complex { re: 0.0, im: 0.0 };
+    let mut points = Vec::new();
+    for row in 0..61 {
+        for column in 0..81 {
+            points.push(Complex { re: -2.2 + column as f64 * 0.035, im: 1.3 - row as f64 * 0.043 });
+        }
+    }
+    for group in points.chunks_exact(LANES) {
+        let group: [Complex<f64>; LANES] = group.try_into().unwrap();
+        let result = escape_time_x4([zero; LANES], group, 500, 2.0);
+        for lane in 0..LANES {
+            assert_eq!(
+                result[lane].map(|(i, _)| i),
+                crate::escape_time(&crate::fractal::Mandelbrot, zero, group[lane], 500, 2.0)
+            );
+        }
+    }
+}
@@ -0,0 +1,60 @@
+/// 一个像素内的采样点相对于像素左上角的偏移, 以像素为单位, 位于 [0, 1) x [0, 1)
+///
+/// 把像素均匀地划分成 samples x samples 个小格子, 每个格子取一个采样点:
+/// jitter 为 None 时取格子的中心; 为 Some(seed) 时在格子内随机取一点 (分层抖动采样), 同样的 seed 总是得到同样的偏移
+/// samples 为 1 且不抖动时只取像素的左上角, 与不做超采样时完全一致
+pub fn sample_offsets(samples: usize, jitter: Option<u64>) -> Vec<(f64, f64)> {
+    assert!(samples > 0);
+    if samples == 1 && jitter.is_none() {
+        return vec![(0.0, 0.0)];
+    }
+
+    let mut state = jitter.unwrap_or(0);
+    let mut offsets = Vec::with_capacity(samples.checked_mul(samples).expect("too many samples"));
+    for j in 0..samples {
+        for i in 0..samples {
+            let (dx, dy) = match jitter {
+                None => (0.5, 0.5),
+                Some(_) => (unit(&mut state), unit(&mut state)),
+            };
+            offsets.push(((i as f64 + dx) / samples as f64, (j as f64 + dy) / samples as f64));
+        }
+    }
+    offsets
+}
+
+/// 根据像素左上角的坐标生成抖动的种子, 这样无论按什么顺序, 由哪个线程渲染, 同一个像素的采样点都相同
+pub fn pixel_seed(re: f64, im: f64) -> u64 {
+    re.to_bits() ^ im.to_bits().rotate_left(32)
+}
+
+/// splitmix64 伪随机数生成器, 返回 [0, 1) 之间均匀分布的数
+pub fn unit(state: &mut u64) -> f64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    // 取高 53 位作为 f64 的尾数
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[test]
+fn test_sample_offsets() {
+    assert_eq!(sample_offsets(1, None), vec![(0.0, 0.0)]);
+    assert_eq!(
+        sample_offsets(2, None),
+        vec![(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)]
+    );
+
+    let jittered = sample_offsets(3, Some(42));
+    assert_eq!(jittered.len(), 9);
+    assert_eq!(jittered, sample_offsets(3, Some(42)));
+    assert_ne!(jittered, sample_offsets(3, Some(43)));
+    // 每个采样点都落在自己的格子里
+    for (index, &(x, y)) in jittered.iter().enumerate() {
+        let (i, j) = ((index % 3) as f64, (index / 3) as f64);
+        assert!(x >= i / 3.0 && x < (i + 1.0) / 3.0);
+        assert!(y >= j / 3.0 && y < (j + 1.0) / 3.0);
+    }
+}
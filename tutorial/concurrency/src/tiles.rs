@@ -0,0 +1,218 @@
+//! 分块渲染超大的图像
+//!
+//! 整幅图像被划分成若干块, 每渲染完一块就写入检查点目录, 中断后重新运行只会渲染缺少的块
+//! 最后按行把所有的块拼接起来, 以流的方式写入 PNG 文件, 任何时候内存中最多只有一行块
+
+use crate::parallel::Backend;
+use crate::{RenderError, RenderOptions};
+use num::Complex;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// 把 bounds 大小的图像划分成 tile_size x tile_size 的块, 最右边一列和最下边一行的块可能更小
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tiling {
+    pub bounds: (usize, usize),
+    pub tile_size: usize,
+}
+
+impl Tiling {
+    /// 块的列数和行数
+    pub fn grid(&self) -> (usize, usize) {
+        (self.bounds.0.div_ceil(self.tile_size), self.bounds.1.div_ceil(self.tile_size))
+    }
+
+    /// 第 row 行第 column 列的块左上角的像素坐标, 以及块的宽和高
+    pub fn tile(&self, column: usize, row: usize) -> ((usize, usize), (usize, usize)) {
+        let origin = (column * self.tile_size, row * self.tile_size);
+        let size = (
+            self.tile_size.min(self.bounds.0 - origin.0),
+            self.tile_size.min(self.bounds.1 - origin.1),
+        );
+        (origin, size)
+    }
+}
+
+#[test]
+fn test_tiling() {
+    let tiling = Tiling { bounds: (250, 100), tile_size: 64 };
+    assert_eq!(tiling.grid(), (4, 2));
+    assert_eq!(tiling.tile(0, 0), ((0, 0), (64, 64)));
+    assert_eq!(tiling.tile(3, 1), ((192, 64), (58, 36)));
+}
+
+/// 检查点目录中记录渲染参数的文件, 用来避免把不同渲染的块混在一起
+const MANIFEST: &str = "render.txt";
+
+/// 保存已完成的块的检查点目录, 每一块都是一个原始像素文件
+pub struct Checkpoint {
+    dir: PathBuf,
+    tiling: Tiling,
+    channels: usize,
+}
+
+impl Checkpoint {
+    /// 打开 (必要时创建) 检查点目录
+    ///
+    /// description 描述了这次渲染的参数, 目录中已有的记录与它不一致时返回错误, 而不是拼出一幅错乱的图像
+    pub fn open(dir: &Path, tiling: Tiling, channels: usize, description: &str) -> io::Result<Checkpoint> {
+        fs::create_dir_all(dir)?;
+        let manifest = format!(
+            "{}x{} tile {} channels {}\n{}\n",
+            tiling.bounds.0, tiling.bounds.1, tiling.tile_size, channels, description
+        );
+        let path = dir.join(MANIFEST);
+        match fs::read_to_string(&path) {
+            Ok(existing) if existing != manifest => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("checkpoint directory {} belongs to a different render", dir.display()),
+                ))
+            }
+            Ok(_) => {}
+            Err(error) if error.kind() == io::ErrorKind::NotFound => fs::write(&path, manifest)?,
+            Err(error) => return Err(error),
+        }
+        Ok(Checkpoint { dir: dir.to_path_buf(), tiling, channels })
+    }
+
+    fn tile_path(&self, column: usize, row: usize) -> PathBuf {
+        self.dir.join(format!("tile_{:05}_{:05}.raw", row, column))
+    }
+
+    fn tile_len(&self, column: usize, row: usize) -> usize {
+        let (_, size) = self.tiling.tile(column, row);
+        size.0 * size.1 * self.channels
+    }
+
+    /// 这一块是否已经完整地保存在目录中
+    fn has_tile(&self, column: usize, row: usize) -> bool {
+        fs::metadata(self.tile_path(column, row))
+            .is_ok_and(|metadata| metadata.len() == self.tile_len(column, row) as u64)
+    }
+
+    /// 渲染所有缺少的块, 返回这次渲染的块数
+    ///
+    /// 每一块先写入临时文件再改名, 所以中断时不会留下写了一半的块
+    /// 已有的块计入 options.progress 的进度; 渲染被取消时丢弃正在渲染的块, 直接返回
+    pub fn render_missing(
+        &self,
+        upper_left: Complex<f64>,
+        lower_right: Complex<f64>,
+        backend: Backend,
+        options: &RenderOptions,
+        threads: usize,
+    ) -> Result<usize, RenderError> {
+        let (columns, rows) = self.tiling.grid();
+        let (existing, missing): (Vec<_>, Vec<_>) = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .partition(|&(column, row)| self.has_tile(column, row));
+        for &(column, row) in &existing {
+            options.skip(self.tiling.tile(column, row).1 .1);
+        }
+        for (done, &(column, row)) in missing.iter().enumerate() {
+            let (origin, size) = self.tiling.tile(column, row);
+            let mut pixels = vec![0; self.tile_len(column, row)];
+            let corners = (upper_left, lower_right);
+            backend.render_region(&mut pixels, self.tiling.bounds, (origin, size), corners, options, threads)?;
+            if options.cancelled() {
+                return Ok(done);
+            }
+
+            let path = self.tile_path(column, row);
+            let partial = path.with_extension("part");
+            fs::write(&partial, &pixels).map_err(RenderError::io(partial.display()))?;
+            fs::rename(&partial, &path).map_err(RenderError::io(path.display()))?;
+        }
+        Ok(missing.len())
+    }
+
+    /// 把所有的块拼接成 PNG 文件, 每次只读入一行块, 按像素行写入编码器; text 会作为 tEXt 块写入
+    pub fn write_png(&self, filename: &str, text: &[(String, String)]) -> io::Result<()> {
+        let (width, height) = self.tiling.bounds;
+        let mut encoder = png::Encoder::new(BufWriter::new(fs::File::create(filename)?), width as u32, height as u32);
+        encoder.set_color(if self.channels == 1 { png::ColorType::Grayscale } else { png::ColorType::Rgb });
+        encoder.set_depth(png::BitDepth::Eight);
+        for (keyword, value) in text {
+            encoder.add_text_chunk(keyword.clone(), value.clone())?;
+        }
+        let mut stream = encoder.write_header()?.into_stream_writer()?;
+
+        let row_len = width * self.channels;
+        let (columns, rows) = self.tiling.grid();
+        for row in 0..rows {
+            let (_, (_, band_height)) = self.tiling.tile(0, row);
+            let mut band = vec![0; row_len * band_height];
+            for column in 0..columns {
+                let ((x, _), (tile_width, _)) = self.tiling.tile(column, row);
+                let tile = fs::read(self.tile_path(column, row))?;
+                if tile.len() != self.tile_len(column, row) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("tile ({}, {}) is incomplete", column, row)));
+                }
+                let tile_row_len = tile_width * self.channels;
+                for (line, source) in tile.chunks(tile_row_len).enumerate() {
+                    let start = line * row_len + x * self.channels;
+                    band[start..start + tile_row_len].copy_from_slice(source);
+                }
+            }
+            stream.write_all(&band)?;
+        }
+        stream.finish()?;
+        Ok(())
+    }
+
+    /// 删除目录中的块和记录文件, 目录中没有别的文件时把目录也删掉
+    pub fn remove(self) -> io::Result<()> {
+        let (columns, rows) = self.tiling.grid();
+        for row in 0..rows {
+            for column in 0..columns {
+                fs::remove_file(self.tile_path(column, row))?;
+            }
+        }
+        fs::remove_file(self.dir.join(MANIFEST))?;
+        let _ = fs::remove_dir(&self.dir);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_checkpoint_resume() {
+    use crate::palette::Palette;
+
+    let dir = std::env::temp_dir().join(format!("tiles-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let tiling = Tiling { bounds: (50, 30), tile_size: 16 };
+    let options = RenderOptions { palette: Palette::Fire, ..RenderOptions::default() };
+    let (upper_left, lower_right) = (Complex { re: -2.0, im: 1.2 }, Complex { re: 1.0, im: -1.2 });
+
+    let checkpoint = Checkpoint::open(&dir, tiling, 3, "test").unwrap();
+    assert_eq!(checkpoint.render_missing(upper_left, lower_right, Backend::Single, &options, 1).unwrap(), 8);
+    let whole = dir.join("whole.png");
+    checkpoint.write_png(whole.to_str().unwrap(), &[]).unwrap();
+
+    // 模拟中断: 删掉一块, 并留下一个写了一半的块, 恢复时只会重新渲染这两块
+    fs::remove_file(checkpoint.tile_path(1, 1)).unwrap();
+    fs::write(checkpoint.tile_path(3, 0), [0; 10]).unwrap();
+    let checkpoint = Checkpoint::open(&dir, tiling, 3, "test").unwrap();
+    assert_eq!(checkpoint.render_missing(upper_left, lower_right, Backend::Rayon, &options, 2).unwrap(), 2);
+    let resumed = dir.join("resumed.png");
+    checkpoint.write_png(resumed.to_str().unwrap(), &[]).unwrap();
+    assert_eq!(fs::read(&whole).unwrap(), fs::read(&resumed).unwrap());
+
+    // 拼接后的图像与一次渲染整幅图像的结果逐位一致, 块与块之间没有接缝
+    let decoder = png::Decoder::new(fs::File::open(&resumed).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+    let mut decoded = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut decoded).unwrap();
+    let mut expected = vec![0; decoded.len()];
+    crate::render(&mut expected, tiling.bounds, upper_left, lower_right, &options);
+    assert!(decoded == expected);
+
+    // 参数不同的渲染不能复用这个目录
+    assert!(Checkpoint::open(&dir, tiling, 3, "another").is_err());
+    fs::remove_file(whole).unwrap();
+    fs::remove_file(resumed).unwrap();
+    checkpoint.remove().unwrap();
+    assert!(!dir.exists());
+}
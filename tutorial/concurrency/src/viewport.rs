@@ -0,0 +1,211 @@
+use crate::error::RenderError;
+use num::Complex;
+
+/// 缩放倍数为 1 时, 视口在虚轴方向上的半高
+const BASE_HALF_HEIGHT: f64 = 2.0;
+
+/// 用中心, 缩放倍数和旋转角度描述的视口
+///
+/// 实轴方向的范围总是按图像的宽高比计算, 所以不会把图像拉伸变形
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub center: Complex<f64>,
+    /// 缩放倍数为 1 时虚轴方向覆盖 [-2, 2], 倍数越大覆盖的范围越小
+    pub zoom: f64,
+    /// 视口绕中心逆时针旋转的角度, 单位是度
+    pub rotation: f64,
+}
+
+/// 绕 pivot 的旋转, turn 是模为 1 的复数 e^(iθ)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rotation {
+    pub pivot: Complex<f64>,
+    pub turn: Complex<f64>,
+}
+
+impl Rotation {
+    pub fn new(pivot: Complex<f64>, degrees: f64) -> Rotation {
+        Rotation { pivot, turn: Complex::from_polar(1.0, degrees.to_radians()) }
+    }
+
+    pub fn apply(&self, point: Complex<f64>) -> Complex<f64> {
+        self.pivot + (point - self.pivot) * self.turn
+    }
+}
+
+impl Viewport {
+    /// 不考虑旋转时视口的左上角和右下角
+    pub fn corners(&self, bounds: (usize, usize)) -> (Complex<f64>, Complex<f64>) {
+        let half_height = BASE_HALF_HEIGHT / self.zoom;
+        let half_width = half_height * bounds.0 as f64 / bounds.1 as f64;
+        (
+            Complex { re: self.center.re - half_width, im: self.center.im + half_height },
+            Complex { re: self.center.re + half_width, im: self.center.im - half_height },
+        )
+    }
+
+    /// 视口的旋转, 角度为 0 时返回 None
+    pub fn rotation(&self) -> Option<Rotation> {
+        if self.rotation == 0.0 {
+            None
+        } else {
+            Some(Rotation::new(self.center, self.rotation))
+        }
+    }
+}
+
+#[test]
+fn test_viewport_corners() {
+    let center = Complex { re: -0.5, im: 0.25 };
+    let viewport = Viewport { center, zoom: 1.0, rotation: 0.0 };
+    assert_eq!(
+        viewport.corners((400, 200)),
+        (Complex { re: -4.5, im: 2.25 }, Complex { re: 3.5, im: -1.75 })
+    );
+    let viewport = Viewport { center, zoom: 4.0, rotation: 0.0 };
+    assert_eq!(
+        viewport.corners((100, 100)),
+        (Complex { re: -1.0, im: 0.75 }, Complex { re: 0.0, im: -0.25 })
+    );
+}
+
+/// 复平面矩形的宽高比与图像宽高比的比值, 等于 1 表示图像不会变形
+pub fn aspect_distortion(bounds: (usize, usize), upper_left: Complex<f64>, lower_right: Complex<f64>) -> f64 {
+    let plane = (lower_right.re - upper_left.re) / (upper_left.im - lower_right.im);
+    let image = bounds.0 as f64 / bounds.1 as f64;
+    plane / image
+}
+
+/// 保持中心不变, 扩大较短的一边, 使复平面矩形的宽高比与图像一致
+pub fn fix_aspect(
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+) -> (Complex<f64>, Complex<f64>) {
+    let center = (upper_left + lower_right) / 2.0;
+    let mut half_width = (lower_right.re - upper_left.re) / 2.0;
+    let mut half_height = (upper_left.im - lower_right.im) / 2.0;
+    let distortion = aspect_distortion(bounds, upper_left, lower_right);
+    if distortion > 1.0 {
+        half_height *= distortion;
+    } else {
+        half_width /= distortion;
+    }
+    (
+        Complex { re: center.re - half_width, im: center.im + half_height },
+        Complex { re: center.re + half_width, im: center.im - half_height },
+    )
+}
+
+#[test]
+fn test_fix_aspect() {
+    let upper_left = Complex { re: -1.0, im: 1.0 };
+    let lower_right = Complex { re: 1.0, im: -1.0 };
+    assert_eq!(aspect_distortion((100, 100), upper_left, lower_right), 1.0);
+    assert_eq!(aspect_distortion((200, 100), upper_left, lower_right), 0.5);
+    // 图像更宽, 所以要把实轴方向扩大一倍
+    assert_eq!(
+        fix_aspect((200, 100), upper_left, lower_right),
+        (Complex { re: -2.0, im: 1.0 }, Complex { re: 2.0, im: -1.0 })
+    );
+    // 图像更高, 所以要把虚轴方向扩大一倍
+    assert_eq!(
+        fix_aspect((100, 200), upper_left, lower_right),
+        (Complex { re: -1.0, im: 2.0 }, Complex { re: 1.0, im: -2.0 })
+    );
+}
+
+/// 检查图像尺寸: 宽和高都至少为 1 并且能写入 PNG, RGB 缓冲区的字节数也不会溢出
+pub fn check_bounds(bounds: (usize, usize)) -> Result<(), RenderError> {
+    let invalid = |reason: &str| Err(RenderError::Dimension { bounds, reason: reason.to_string() });
+    if bounds.0 == 0 || bounds.1 == 0 {
+        return invalid("the width and height must be at least 1");
+    }
+    let bytes = bounds.0.checked_mul(bounds.1).and_then(|pixels| pixels.checked_mul(3));
+    if bounds.0 > u32::MAX as usize || bounds.1 > u32::MAX as usize || bytes.is_none_or(|bytes| bytes > isize::MAX as usize) {
+        return invalid("too many pixels");
+    }
+    Ok(())
+}
+
+/// 检查两个角点围成的视口: 坐标都是有限的数, 左上角在右下角的左上方, 并且 f64 能分辨相邻的像素
+pub fn check_corners(bounds: (usize, usize), upper_left: Complex<f64>, lower_right: Complex<f64>) -> Result<(), RenderError> {
+    let invalid = |reason: String| Err(RenderError::Viewport { reason });
+    let corners = format!("{},{} and {},{}", upper_left.re, upper_left.im, lower_right.re, lower_right.im);
+    let coordinates = [upper_left.re, upper_left.im, lower_right.re, lower_right.im];
+    if !coordinates.iter().all(|x| x.is_finite()) {
+        return invalid(format!("the corners {} must be finite", corners));
+    }
+    // 像素间距不大于坐标的 f64 精度时, 相邻的像素会映射到同一个复数; 重合的角点也属于这种情况
+    // 放得过大的视口在计算角点时就可能失去精度而颠倒或重合, 所以先于顺序检查, 以免报告成角点写反了
+    let spacing = ((lower_right.re - upper_left.re).abs() / bounds.0 as f64).min((upper_left.im - lower_right.im).abs() / bounds.1 as f64);
+    let scale = coordinates.iter().fold(0.0f64, |max, x| max.max(x.abs()));
+    if spacing <= scale * f64::EPSILON {
+        return invalid(format!("the pixels between {} are too close together for f64, use --deep", corners));
+    }
+    if upper_left.re >= lower_right.re || upper_left.im <= lower_right.im {
+        return invalid(format!("the corners {} must be the upper left and lower right corners, in that order", corners));
+    }
+    Ok(())
+}
+
+impl Viewport {
+    /// 检查视口并返回它的角点: 旋转角度和缩放倍数都必须是有限的, 放大后相邻的像素在 f64 中也必须能区分开
+    ///
+    /// 角点是由中心和缩放倍数算出来的, 所以错误信息指出缩放倍数, 而不是调用者并没有给出的角点
+    pub fn check(&self, bounds: (usize, usize)) -> Result<(Complex<f64>, Complex<f64>), RenderError> {
+        let invalid = |option: &'static str, value: f64, reason: &str| {
+            Err(RenderError::Options { option, value: value.to_string(), reason: reason.to_string() })
+        };
+        if !self.rotation.is_finite() {
+            return invalid("rotation", self.rotation, "must be finite");
+        }
+        if !(self.zoom > 0.0 && self.zoom.is_finite()) {
+            return invalid("zoom", self.zoom, "must be positive and finite");
+        }
+        let (upper_left, lower_right) = self.corners(bounds);
+        check_corners(bounds, upper_left, lower_right).map_err(|_| {
+            let center = format!("{},{}", self.center.re, self.center.im);
+            let finite = [upper_left.re, upper_left.im, lower_right.re, lower_right.im].iter().all(|x| x.is_finite());
+            let reason = if finite {
+                format!("zoom {:e} around the center {} is too deep for f64 precision", self.zoom, center)
+            } else {
+                format!("zoom {:e} around the center {} gives corners that are not finite", self.zoom, center)
+            };
+            RenderError::Viewport { reason }
+        })?;
+        Ok((upper_left, lower_right))
+    }
+}
+
+#[test]
+fn test_check_viewport() {
+    assert!(check_bounds((1, 1)).is_ok());
+    assert!(matches!(check_bounds((0, 10)), Err(RenderError::Dimension { .. })));
+    assert!(check_bounds((usize::MAX / 2, 4)).is_err());
+
+    let upper_left = Complex { re: -1.0, im: 1.0 };
+    let lower_right = Complex { re: 1.0, im: -1.0 };
+    assert!(check_corners((100, 100), upper_left, lower_right).is_ok());
+    // 角点颠倒, 重合或者不是有限的数
+    for (ul, lr) in [(lower_right, upper_left), (upper_left, upper_left), (upper_left, Complex { re: f64::INFINITY, im: -1.0 })] {
+        assert!(matches!(check_corners((100, 100), ul, lr), Err(RenderError::Viewport { .. })));
+    }
+    // 视口宽度远小于坐标的 f64 精度
+    let near = Complex { re: -0.75 + 1e-15, im: 0.1 - 1e-15 };
+    assert!(check_corners((100, 100), Complex { re: -0.75, im: 0.1 }, near).is_err());
+    // 放得过大时算出的角点可能颠倒, 也要报告成精度不够
+    let error = check_corners((100, 75), Complex { re: -0.5, im: 1e-20 }, Complex { re: -0.5 - 1e-17, im: -1e-20 }).unwrap_err();
+    assert!(error.to_string().contains("too close together"), "{}", error);
+
+    let center = Complex { re: -0.5, im: 0.0 };
+    assert!(Viewport { center, zoom: 4.0, rotation: 30.0 }.check((100, 75)).is_ok());
+    let error = Viewport { center, zoom: 1e20, rotation: 0.0 }.check((100, 75)).unwrap_err();
+    assert_eq!(error.to_string(), "invalid viewport: zoom 1e20 around the center -0.5,0 is too deep for f64 precision");
+    let error = Viewport { center, zoom: 1e-310, rotation: 0.0 }.check((100, 75)).unwrap_err();
+    assert!(error.to_string().contains("not finite"), "{}", error);
+    for rotation in [f64::NAN, f64::INFINITY] {
+        let error = Viewport { center, zoom: 1.0, rotation }.check((100, 75)).unwrap_err();
+        assert!(matches!(error, RenderError::Options { option: "rotation", .. }), "{}", error);
+    }
+}
@@ -0,0 +1,46 @@
+use concurrency::palette::Palette;
+use concurrency::{escape_time, fractal::Mandelbrot, RenderError, RenderOptions, Renderer, Viewport};
+use num::Complex;
+
+// cargo test --test renderer
+// 集成测试: 只通过公共的 Renderer 和 Viewport 使用这个库, 就像嵌入它的程序一样
+#[test]
+fn test_render_viewport() {
+    let options = RenderOptions { palette: Palette::Fire, smooth: true, ..RenderOptions::default() };
+    let renderer = Renderer::new(options);
+    let viewport = Viewport { center: Complex { re: -0.75, im: 0.0 }, zoom: 1.0, rotation: 0.0 };
+    let bounds = (64, 48);
+    let pixels = renderer.render(bounds, &viewport).unwrap();
+    assert_eq!(pixels.len(), bounds.0 * bounds.1 * 3);
+
+    // 中心 -0.75 + 0i 在集合内部, 着色为黑色; 左上角的点很快就逃逸了
+    let center = (bounds.1 / 2 * bounds.0 + bounds.0 / 2) * 3;
+    assert_eq!(&pixels[center..center + 3], [0, 0, 0]);
+    assert_ne!(&pixels[..3], [0, 0, 0]);
+    let zero = Complex { re: 0.0, im: 0.0 };
+    assert_eq!(escape_time(&Mandelbrot, zero, Complex { re: -0.75, im: 0.0 }, 255, 2.0), None);
+
+    // 结果与线程数无关
+    let single = Renderer { threads: 1, ..renderer.clone() };
+    assert_eq!(single.render(bounds, &viewport).unwrap(), pixels);
+}
+
+#[test]
+fn test_render_errors() {
+    let renderer = Renderer::default();
+    let viewport = Viewport { center: Complex { re: 0.0, im: 0.0 }, zoom: 1.0, rotation: 0.0 };
+    let error = renderer.render((0, 10), &viewport).unwrap_err();
+    assert_eq!(error.exit_code(), 3);
+    let reversed = renderer.render_corners((10, 10), Complex { re: 1.0, im: -1.0 }, Complex { re: -1.0, im: 1.0 });
+    assert!(matches!(reversed, Err(RenderError::Viewport { .. })));
+
+    // 不合法的渲染参数同样返回错误, 而不是在渲染线程中 panic
+    let error = Renderer { threads: 0, ..Renderer::default() }.render((10, 10), &viewport).unwrap_err();
+    assert_eq!(error.to_string(), "invalid value '0' for threads: must be positive");
+    let options = RenderOptions { samples: 0, ..RenderOptions::default() };
+    assert_eq!(Renderer::new(options).render((10, 10), &viewport).unwrap_err().exit_code(), 2);
+    // 采样点数有上限, 过大时返回错误而不是在分配内存时终止
+    let options = RenderOptions { samples: 100_000, ..RenderOptions::default() };
+    let error = Renderer::new(options).render((100, 75), &viewport).unwrap_err();
+    assert_eq!(error.to_string(), "invalid value '100000' for samples: must be at most 64");
+}